@@ -0,0 +1,196 @@
+//! images stored inside zip and tar archives, addressed as `archive.zip!/inner/path.png`
+use super::*;
+use flate2::read::GzDecoder;
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{self, BufReader, Read},
+    os::unix::ffi::OsStrExt,
+};
+
+/// separates the archive from the member in an entry path
+pub const MEMBER_SEPARATOR: &str = "!/";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    /// `.zip` and `.cbz` comic books
+    Zip,
+    /// `.tar` and `.cbt` comic books
+    Tar,
+    /// `.tar.gz` and `.tgz`
+    TarGz,
+}
+impl ArchiveKind {
+    /// tells the kind from the file name, ignoring case
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        let ext = name.rsplit_once('.')?.1;
+        match ext {
+            "zip" | "cbz" => Some(Self::Zip),
+            "tar" | "cbt" => Some(Self::Tar),
+            "tgz" => Some(Self::TarGz),
+            "gz" if name.ends_with(".tar.gz") => Some(Self::TarGz),
+            _ => None,
+        }
+    }
+}
+
+/// `archive!/inner`
+pub fn member_path(archive: &Path, inner: &str) -> PathBuf {
+    let mut path = archive.as_os_str().to_os_string();
+    path.push(MEMBER_SEPARATOR);
+    path.push(inner);
+    PathBuf::from(path)
+}
+
+/// # Description
+/// splits an entry path into the archive and the member inside it
+/// ## returns
+/// `None` for plain files, a `!/` only counts when what's in front of it is named like an archive
+pub fn split_member_path(path: &Path) -> Option<(&Path, &str)> {
+    let bytes = path.as_os_str().as_bytes();
+    let separator = MEMBER_SEPARATOR.as_bytes();
+    (0..bytes.len().saturating_sub(1))
+        .filter(|&i| bytes[i..].starts_with(separator))
+        .map(|i| {
+            (
+                Path::new(OsStr::from_bytes(&bytes[..i])),
+                &bytes[i + separator.len()..],
+            )
+        })
+        .find(|(archive, _)| ArchiveKind::from_path(archive).is_some())
+        .and_then(|(archive, inner)| Some((archive, std::str::from_utf8(inner).ok()?)))
+}
+
+fn invalid(err: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn kind_of(archive: &Path) -> io::Result<ArchiveKind> {
+    ArchiveKind::from_path(archive).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} isn't an archive", archive),
+        )
+    })
+}
+
+fn open_tar(kind: ArchiveKind, file: BufReader<File>) -> tar::Archive<Box<dyn Read>> {
+    match kind {
+        ArchiveKind::TarGz => tar::Archive::new(Box::new(GzDecoder::new(file))),
+        _ => tar::Archive::new(Box::new(file)),
+    }
+}
+
+/// # Description
+/// calls `visit` with the name and contents of every regular file in `archive`, in archive order
+/// ## Comments
+/// members are streamed one at a time, nothing is extracted to disk
+pub fn for_each_member<F>(archive: &Path, mut visit: F) -> io::Result<()>
+where
+    F: FnMut(&str, &mut dyn Read),
+{
+    let kind = kind_of(archive)?;
+    let file = BufReader::new(File::open(archive)?);
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(file).map_err(invalid)?;
+            for idx in 0..zip.len() {
+                let mut member = zip.by_index(idx).map_err(invalid)?;
+                if member.is_file() {
+                    let name = member.name().to_string();
+                    visit(&name, &mut member);
+                }
+            }
+            Ok(())
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            for entry in open_tar(kind, file).entries()? {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let name = entry.path()?.to_string_lossy().into_owned();
+                visit(&name, &mut entry);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// # Description
+/// contents of the member `archive!/inner`
+/// ## Comments
+/// zip members are looked up in the central directory, tar archives are only read up to the member
+/// ## returns
+/// a `NotFound` error if the archive has no such member
+pub fn read_member(archive: &Path, inner: &str) -> io::Result<Vec<u8>> {
+    let kind = kind_of(archive)?;
+    let file = BufReader::new(File::open(archive)?);
+    let mut bytes = vec![];
+    match kind {
+        ArchiveKind::Zip => {
+            let mut zip = zip::ZipArchive::new(file).map_err(invalid)?;
+            match zip.by_name(inner) {
+                Ok(mut member) if member.is_file() => {
+                    member.read_to_end(&mut bytes)?;
+                    return Ok(bytes);
+                }
+                Ok(_) | Err(zip::result::ZipError::FileNotFound) => {}
+                Err(err) => return Err(invalid(err)),
+            };
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            for entry in open_tar(kind, file).entries()? {
+                let mut entry = entry?;
+                if entry.header().entry_type().is_file() && entry.path()?.to_string_lossy() == inner
+                {
+                    entry.read_to_end(&mut bytes)?;
+                    return Ok(bytes);
+                }
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{:?} has no member {:?}", archive, inner),
+    ))
+}
+
+#[test]
+fn archive_members_round_trip() {
+    use std::io::Write;
+    let dir = TempDir::new("archive");
+    let png = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/test_set/a.png")).unwrap();
+
+    let cbz = dir.join("Issue 1.CBZ");
+    let mut zip = zip::ZipWriter::new(File::create(&cbz).unwrap());
+    zip.add_directory("pages/", zip::write::SimpleFileOptions::default())
+        .unwrap();
+    zip.start_file("pages/01.png", zip::write::SimpleFileOptions::default())
+        .unwrap();
+    zip.write_all(&png).unwrap();
+    zip.finish().unwrap();
+
+    let tar_path = dir.join("bundle.tar");
+    let mut tar = tar::Builder::new(File::create(&tar_path).unwrap());
+    tar.append_path_with_name(
+        concat!(env!("CARGO_MANIFEST_DIR"), "/test_set/a.png"),
+        "x/a.png",
+    )
+    .unwrap();
+    tar.finish().unwrap();
+    drop(tar);
+
+    for (archive, inner) in [(&cbz, "pages/01.png"), (&tar_path, "x/a.png")] {
+        let mut names = vec![];
+        for_each_member(archive, |name, _| names.push(name.to_string())).unwrap();
+        assert_eq!(names, [inner]);
+        let path = member_path(archive, inner);
+        assert_eq!(split_member_path(&path), Some((archive.as_path(), inner)));
+        assert_eq!(read_member(archive, inner).unwrap(), png);
+    }
+    assert_eq!(split_member_path(Path::new("wow!/a.png")), None);
+    assert!(read_member(&cbz, "missing.png").is_err());
+    assert!(read_member(&tar_path, "missing.png").is_err());
+}
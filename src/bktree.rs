@@ -0,0 +1,122 @@
+use super::*;
+use std::collections::BinaryHeap;
+
+struct BKNode {
+    hash: u64,
+    /// every item that has exactly this hash
+    items: Vec<usize>,
+    /// (bit distance to this node, index of child)
+    children: Vec<(u32, usize)>,
+}
+
+/// # Description
+/// Burkhard-Keller tree over 64-bit hashes, used as an exact index for nearest neighbour queries
+/// ## Comments
+/// the tree is keyed by `perceptual::bit_distance` because the rescaled `hamming_distance` rounds down
+/// and would break the triangle inequality the pruning relies on
+pub struct BKTree {
+    nodes: Vec<BKNode>,
+}
+impl BKTree {
+    pub fn new() -> Self {
+        Self { nodes: vec![] }
+    }
+
+    /// builds a tree where every item is the index of its entry in `entries`
+    pub fn from_entries(entries: &[ImageEntry]) -> Self {
+        let mut tree = Self::new();
+        for (idx, entry) in entries.iter().enumerate() {
+            tree.insert(entry.hash, idx);
+        }
+        tree
+    }
+
+    pub fn insert(&mut self, hash: u64, item: usize) {
+        if self.nodes.is_empty() {
+            self.nodes.push(BKNode {
+                hash,
+                items: vec![item],
+                children: vec![],
+            });
+            return;
+        }
+
+        let mut cur = 0;
+        loop {
+            let dist = perceptual::bit_distance(hash, self.nodes[cur].hash);
+            if dist == 0 {
+                self.nodes[cur].items.push(item);
+                return;
+            }
+            let child = self.nodes[cur]
+                .children
+                .iter()
+                .find(|&&(d, _)| d == dist)
+                .map(|&(_, child)| child);
+            match child {
+                Some(child) => cur = child,
+                None => {
+                    let new_node = self.nodes.len();
+                    self.nodes.push(BKNode {
+                        hash,
+                        items: vec![item],
+                        children: vec![],
+                    });
+                    self.nodes[cur].children.push((dist, new_node));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// # Description
+    /// finds the `k` items closest to `hash`, ignoring items rejected by `filter`
+    /// ## returns
+    /// `(item, bit distance)` pairs sorted by distance, ties are broken by the smaller item
+    pub fn nearest<F: Fn(usize) -> bool>(
+        &self,
+        hash: u64,
+        k: usize,
+        filter: F,
+    ) -> Vec<(usize, u32)> {
+        // max-heap so the worst of the current best k sits on top
+        let mut best = BinaryHeap::<(u32, usize)>::with_capacity(k + 1);
+        if k == 0 || self.nodes.is_empty() {
+            return vec![];
+        }
+
+        let mut stack = vec![0];
+        while let Some(cur) = stack.pop() {
+            let node = &self.nodes[cur];
+            let dist = perceptual::bit_distance(hash, node.hash);
+            for &item in node.items.iter().filter(|&&item| filter(item)) {
+                if best.len() < k {
+                    best.push((dist, item));
+                } else if best.peek().is_some_and(|&top| (dist, item) < top) {
+                    best.pop();
+                    best.push((dist, item));
+                }
+            }
+
+            let radius = if best.len() < k {
+                u64::BITS
+            } else {
+                best.peek().map(|&(d, _)| d).unwrap_or(u64::BITS)
+            };
+            for &(child_dist, child) in &node.children {
+                if child_dist + radius >= dist && child_dist <= dist + radius {
+                    stack.push(child);
+                }
+            }
+        }
+
+        let mut best = best.into_vec();
+        best.sort_unstable();
+        best.into_iter().map(|(dist, item)| (item, dist)).collect()
+    }
+}
+impl Default for BKTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,318 @@
+//! on-disk cache of hashes so unchanged images don't have to be decoded again
+use super::*;
+use crate::perceptual::HashConfig;
+use path_absolutize::*;
+use std::{
+    env,
+    fs::{self, File, Metadata},
+    io::{self, BufReader, BufWriter},
+    time::UNIX_EPOCH,
+};
+
+/// bumped whenever the layout of the cache file changes
+/// ## Comments
+/// - 1: hashes written as json numbers, the file had no `version` yet
+/// - 2: hashes written as 16 hex digit strings
+pub const CACHE_VERSION: u32 = 2;
+/// oldest version `HashCache::open` still understands
+pub const MIN_CACHE_VERSION: u32 = 1;
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    /// modification time as (seconds, nanoseconds) since the unix epoch
+    pub mtime: (u64, u32),
+    /// index into the `HashConfig` table of the cache
+    pub config: usize,
+    #[serde(with = "hexhash")]
+    pub hash: u64,
+}
+
+/// blake3 digest of a file's contents, they don't depend on the hash config
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct DigestEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    pub mtime: (u64, u32),
+    /// as hex
+    pub blake3: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    #[serde(default = "unversioned")]
+    version: u32,
+    configs: Vec<HashConfig>,
+    entries: Vec<CacheEntry>,
+    // missing in caches written before exact duplicates were found
+    #[serde(default)]
+    digests: Vec<DigestEntry>,
+}
+
+/// # Description
+/// hashes keyed by absolute path, an entry is only trusted while the file's size and mtime are unchanged
+/// and it was produced by the same `HashConfig` as the lookup
+pub struct HashCache {
+    location: PathBuf,
+    configs: Vec<HashConfig>,
+    entries: HashMap<PathBuf, CacheEntry>,
+    digests: HashMap<PathBuf, DigestEntry>,
+    dirty: bool,
+}
+impl HashCache {
+    /// `$XDG_CACHE_HOME/image_grouper/hashes.json`, falling back to `~/.cache`
+    pub fn default_location() -> Option<PathBuf> {
+        Some(cache_home()?.join("hashes.json"))
+    }
+
+    /// an empty cache that will be saved to `location`
+    pub fn new<P: AsRef<Path>>(location: P) -> Self {
+        Self {
+            location: location.as_ref().to_path_buf(),
+            configs: vec![],
+            entries: HashMap::new(),
+            digests: HashMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// # Description
+    /// loads the cache stored at `location`, a missing file is an empty cache
+    /// ## returns
+    /// an `InvalidData` error for caches written by an incompatible version of the format
+    pub fn open<P: AsRef<Path>>(location: P) -> io::Result<Self> {
+        let location = location.as_ref().to_path_buf();
+        let cache_file: CacheFile = match File::open(&location) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::new(location)),
+            Err(err) => return Err(err),
+        };
+        if !(MIN_CACHE_VERSION..=CACHE_VERSION).contains(&cache_file.version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "cache format version {} is not supported, expected {} to {}",
+                    cache_file.version, MIN_CACHE_VERSION, CACHE_VERSION
+                ),
+            ));
+        }
+        // older layouts only differ in how hashes are written, which deserialization already handles,
+        // the next save writes the current one
+        let configs = cache_file.configs;
+        let entries = cache_file
+            .entries
+            .into_iter()
+            .filter(|entry| entry.config < configs.len())
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+        let digests = cache_file
+            .digests
+            .into_iter()
+            .map(|entry| (entry.path.clone(), entry))
+            .collect();
+        Ok(Self {
+            location,
+            configs,
+            entries,
+            digests,
+            dirty: false,
+        })
+    }
+
+    pub fn location(&self) -> &Path {
+        &self.location
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// every config that produced at least one of the cached hashes
+    pub fn configs(&self) -> &[HashConfig] {
+        &self.configs
+    }
+
+    /// # Description
+    /// the cached hashes that were made with `config`, regardless of whether the files still exist
+    /// ## returns
+    /// entries sorted by path
+    pub fn entries_for(&self, config: &HashConfig) -> Vec<ImageEntry> {
+        let mut entries = self
+            .entries
+            .values()
+            .filter(|entry| self.configs[entry.config] == *config)
+            .map(|entry| ImageEntry {
+                hash: entry.hash,
+                path: entry.path.clone(),
+                aliases: vec![],
+            })
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+        entries
+    }
+
+    /// # Description
+    /// cached hash of `path`
+    /// ## returns
+    /// `None` if there is no entry or the file changed since it was hashed
+    pub fn lookup(&self, path: &Path, metadata: &Metadata, config: &HashConfig) -> Option<u64> {
+        let entry = self.entries.get(absolute(path)?.as_path())?;
+        let unchanged = entry.size == metadata.len()
+            && Some(entry.mtime) == mtime(metadata)
+            && self.configs[entry.config] == *config;
+        unchanged.then_some(entry.hash)
+    }
+
+    pub fn insert(&mut self, path: &Path, metadata: &Metadata, config: &HashConfig, hash: u64) {
+        let (path, mtime) = match absolute(path).zip(mtime(metadata)) {
+            Some(key) => key,
+            None => return,
+        };
+        let config = match self.configs.iter().position(|c| c == config) {
+            Some(idx) => idx,
+            None => {
+                self.configs.push(config.clone());
+                self.configs.len() - 1
+            }
+        };
+        let entry = CacheEntry {
+            path: path.clone(),
+            size: metadata.len(),
+            mtime,
+            config,
+            hash,
+        };
+        if self.entries.get(&path) != Some(&entry) {
+            self.entries.insert(path, entry);
+            self.dirty = true;
+        }
+    }
+
+    /// # Description
+    /// cached blake3 digest of the contents of `path`
+    /// ## returns
+    /// `None` if there is no digest or the file changed since it was read
+    pub fn lookup_digest(&self, path: &Path, metadata: &Metadata) -> Option<blake3::Hash> {
+        let entry = self.digests.get(absolute(path)?.as_path())?;
+        let unchanged = entry.size == metadata.len() && Some(entry.mtime) == mtime(metadata);
+        unchanged
+            .then(|| blake3::Hash::from_hex(&entry.blake3).ok())
+            .flatten()
+    }
+
+    pub fn insert_digest(&mut self, path: &Path, metadata: &Metadata, digest: blake3::Hash) {
+        let (path, mtime) = match absolute(path).zip(mtime(metadata)) {
+            Some(key) => key,
+            None => return,
+        };
+        let entry = DigestEntry {
+            path: path.clone(),
+            size: metadata.len(),
+            mtime,
+            blake3: digest.to_hex().to_string(),
+        };
+        if self.digests.get(&path) != Some(&entry) {
+            self.digests.insert(path, entry);
+            self.dirty = true;
+        }
+    }
+
+    /// writes the cache back to its location if anything changed
+    /// ## Comments
+    /// writes to a temporary file first so an interrupted save can't corrupt the existing cache
+    pub fn save(&mut self) -> io::Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(parent) = self.location.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut entries = self.entries.values().cloned().collect::<Vec<_>>();
+        entries.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+        let mut digests = self.digests.values().cloned().collect::<Vec<_>>();
+        digests.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+
+        let temp_location = self.location.with_extension("json.tmp");
+        let writer = BufWriter::new(File::create(&temp_location)?);
+        let cache_file = CacheFile {
+            version: CACHE_VERSION,
+            configs: self.configs.clone(),
+            entries,
+            digests,
+        };
+        serde_json::to_writer(writer, &cache_file)?;
+        fs::rename(&temp_location, &self.location)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// `$XDG_CACHE_HOME/image_grouper`, falling back to `~/.cache/image_grouper`, shared by everything kept between runs
+pub(crate) fn cache_home() -> Option<PathBuf> {
+    let cache_home = env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+    Some(cache_home.join("image_grouper"))
+}
+
+fn unversioned() -> u32 {
+    1
+}
+
+fn absolute(path: &Path) -> Option<PathBuf> {
+    path.absolutize().ok().map(|path| path.into_owned())
+}
+
+pub(crate) fn mtime(metadata: &Metadata) -> Option<(u64, u32)> {
+    let since_epoch = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+#[test]
+fn cache_round_trip() {
+    let dir = TempDir::new("cache");
+    let location = dir.join("hashes.json");
+    let image = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test_set/a.png"));
+    let metadata = fs::metadata(image).unwrap();
+
+    let mut cache = HashCache::open(&location).unwrap();
+    assert!(cache.is_empty());
+    cache.insert(image, &metadata, &HashConfig::new(HashType::AHASH), 42);
+    cache.insert_digest(image, &metadata, blake3::hash(b"contents"));
+    cache.save().unwrap();
+
+    let cache = HashCache::open(&location).unwrap();
+    let mut config = HashConfig::new(HashType::AHASH);
+    assert_eq!(cache.lookup(image, &metadata, &config), Some(42));
+    assert_eq!(
+        cache.lookup_digest(image, &metadata),
+        Some(blake3::hash(b"contents"))
+    );
+    config.filter = perceptual::ResizeFilter::Nearest;
+    assert_eq!(cache.lookup(image, &metadata, &config), None);
+    assert_eq!(
+        cache.lookup(image, &metadata, &HashConfig::new(HashType::DHASH)),
+        None
+    );
+
+    // written before hashes became hex strings
+    let mut legacy: serde_json::Value =
+        serde_json::from_slice(&fs::read(&location).unwrap()).unwrap();
+    legacy.as_object_mut().unwrap().remove("version");
+    legacy["entries"][0]["hash"] = 42.into();
+    fs::write(&location, legacy.to_string()).unwrap();
+    let cache = HashCache::open(&location).unwrap();
+    assert_eq!(
+        cache.lookup(image, &metadata, &HashConfig::new(HashType::AHASH)),
+        Some(42)
+    );
+    legacy["version"] = (CACHE_VERSION + 1).into();
+    fs::write(&location, legacy.to_string()).unwrap();
+    assert!(HashCache::open(&location).is_err());
+}
@@ -0,0 +1,181 @@
+//! byte-identical files, found before any image is decoded
+use super::*;
+use crate::{cache::HashCache, filesysutils::InputFile};
+use rayon::prelude::*;
+use std::{
+    fs::{self, File},
+    io,
+};
+
+/// copies of the same bytes
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct DuplicateSet {
+    pub size: u64,
+    /// blake3 digest of the contents, as hex
+    pub blake3: String,
+    /// one path per copy, the first one names the merged image
+    pub paths: Vec<PathBuf>,
+}
+
+fn digest(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// # Description
+/// merges byte-identical files into one input, the copies become aliases of the first one
+/// ## Comments
+/// files are bucketed by size first, only files sharing a size with another one are read and hashed with blake3.
+/// digests of unchanged files come from `cache`, the fresh ones are added to it.
+/// files `candidate` turns down (e.g. videos), empty files, files that can't be read and files not reached
+/// before ctrl-c stay on their own, the order of `files` is kept
+/// ## returns
+/// the merged inputs and every set of copies, ordered like the inputs
+pub fn collapse(
+    files: Vec<InputFile>,
+    cache: Option<&mut HashCache>,
+    candidate: &(dyn Fn(&Path) -> bool + Sync),
+) -> (Vec<InputFile>, Vec<DuplicateSet>) {
+    let metadata = files
+        .par_iter()
+        .map(|file| {
+            fs::metadata(&file.path)
+                .ok()
+                .filter(|metadata| metadata.is_file() && metadata.len() > 0)
+                .filter(|_| candidate(&file.path))
+        })
+        .collect::<Vec<_>>();
+    let mut bucket_sizes: HashMap<u64, usize> = HashMap::new();
+    for metadata in metadata.iter().flatten() {
+        *bucket_sizes.entry(metadata.len()).or_default() += 1;
+    }
+    let cached = cache.as_deref();
+    // the digest along with whether it was read from the cache
+    let keys = files
+        .par_iter()
+        .zip(&metadata)
+        .map(|(file, metadata)| {
+            let metadata = metadata
+                .as_ref()
+                .filter(|metadata| bucket_sizes[&metadata.len()] > 1)?;
+            if let Some(digest) = cached.and_then(|c| c.lookup_digest(&file.path, metadata)) {
+                return Some((digest, true));
+            }
+            if interrupt::requested() {
+                return None;
+            }
+            digest(&file.path).ok().map(|digest| (digest, false))
+        })
+        .collect::<Vec<_>>();
+    if let Some(cache) = cache {
+        for ((file, metadata), key) in files.iter().zip(&metadata).zip(&keys) {
+            if let (Some(metadata), Some((digest, false))) = (metadata, key) {
+                cache.insert_digest(&file.path, metadata, *digest);
+            }
+        }
+    }
+    let keys = metadata
+        .iter()
+        .zip(keys)
+        .map(|(metadata, key)| Some((metadata.as_ref()?.len(), key?.0)))
+        .collect::<Vec<_>>();
+
+    let mut positions = HashMap::new();
+    let mut sets: HashMap<usize, DuplicateSet> = HashMap::new();
+    let mut merged: Vec<InputFile> = vec![];
+    for (file, key) in files.into_iter().zip(keys) {
+        let (size, digest) = match key {
+            Some(key) => key,
+            None => {
+                merged.push(file);
+                continue;
+            }
+        };
+        match positions.get(&(size, digest)) {
+            Some(&pos) => {
+                let first = &mut merged[pos];
+                sets.entry(pos)
+                    .or_insert_with(|| DuplicateSet {
+                        size,
+                        blake3: digest.to_hex().to_string(),
+                        paths: vec![first.path.clone()],
+                    })
+                    .paths
+                    .push(file.path.clone());
+                first.aliases.push(file.path);
+                first.aliases.extend(file.aliases);
+            }
+            None => {
+                positions.insert((size, digest), merged.len());
+                merged.push(file);
+            }
+        }
+    }
+    let mut sets = sets.into_iter().collect::<Vec<_>>();
+    sets.sort_unstable_by_key(|&(pos, _)| pos);
+    (merged, sets.into_iter().map(|(_, set)| set).collect())
+}
+
+#[test]
+fn identical_bytes_collapse() {
+    let root = TempDir::new("duplicates");
+    let file = |name: &str, contents: &[u8], aliases: &[&str]| {
+        fs::write(root.join(name), contents).unwrap();
+        InputFile {
+            path: root.join(name),
+            aliases: aliases.iter().map(|alias| root.join(alias)).collect(),
+        }
+    };
+    let files = vec![
+        file("a.png", b"same", &["link/a.png"]),
+        file("b.png", b"diff", &[]),
+        file("c.png", b"same", &["link/c.png"]),
+        file("d.png", b"unique size", &[]),
+        file("e.png", b"", &[]),
+        file("f.png", b"", &[]),
+        file("g.mp4", b"clip", &[]),
+        file("h.mp4", b"clip", &[]),
+    ];
+    let not_video = |path: &Path| path.extension().is_none_or(|ext| ext != "mp4");
+    let (merged, sets) = collapse(files.clone(), None, &not_video);
+    assert_eq!(
+        merged.iter().map(|file| &file.path).collect::<Vec<_>>(),
+        [0, 1, 3, 4, 5, 6, 7].map(|idx| &files[idx].path)
+    );
+    assert_eq!(
+        merged[0].aliases,
+        ["link/a.png", "c.png", "link/c.png"].map(|alias| root.join(alias))
+    );
+    assert_eq!(
+        sets,
+        [DuplicateSet {
+            size: 4,
+            blake3: blake3::hash(b"same").to_hex().to_string(),
+            paths: vec![root.join("a.png"), root.join("c.png")],
+        }]
+    );
+
+    // digests of unchanged files are read back instead of the files
+    let mut cache = HashCache::new(root.join("hashes.json"));
+    assert_eq!(
+        collapse(files.clone(), Some(&mut cache), &not_video),
+        (merged, sets)
+    );
+    let metadata = fs::metadata(root.join("b.png")).unwrap();
+    assert_eq!(
+        cache.lookup_digest(&root.join("b.png"), &metadata),
+        Some(blake3::hash(b"diff"))
+    );
+    assert_eq!(
+        cache.lookup_digest(
+            &root.join("d.png"),
+            &fs::metadata(root.join("d.png")).unwrap()
+        ),
+        None
+    );
+    cache.insert_digest(&root.join("b.png"), &metadata, blake3::hash(b"same"));
+    // a cached digest is trusted while the file is unchanged, so b.png now reads as a copy of a.png
+    let (merged, _) = collapse(files, Some(&mut cache), &not_video);
+    assert_eq!(merged[0].aliases[1], root.join("b.png"));
+}
@@ -0,0 +1,345 @@
+//! writers that dump the similarity graph into formats understood by graph tools (Graphviz, Gephi, networkx)
+use super::*;
+use crate::perceptual::HashConfig;
+use std::io::{self, Write};
+
+/// version of the node-link json layout, 2 switched hashes to hex strings
+pub const NODE_LINK_VERSION: u32 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Dot,
+    GraphML,
+    Json,
+    /// not a graph format, written by `sqlite::write_sqlite` from the whole index
+    Sqlite,
+}
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "dot" => Some(Self::Dot),
+            "graphml" => Some(Self::GraphML),
+            "json" => Some(Self::Json),
+            "sqlite" => Some(Self::Sqlite),
+            _ => None,
+        }
+    }
+}
+
+/// # Description
+/// a borrowed view of everything needed to export a graph
+/// ## Comments
+/// nodes are indices into `nodes`, nodes missing from `groups` are treated as their own group.
+/// `config` is written as graph level attributes so the hashes can't be mistaken for another config's
+pub struct GraphExport<'a> {
+    pub config: &'a HashConfig,
+    pub nodes: &'a [ImageEntry],
+    pub edges: Vec<(usize, usize, u64)>,
+    pub groups: &'a HashMap<usize, GroupID>,
+}
+impl<'a> GraphExport<'a> {
+    pub fn new(
+        config: &'a HashConfig,
+        nodes: &'a [ImageEntry],
+        edges: impl Iterator<Item = (usize, usize, u64)>,
+        groups: &'a HashMap<usize, GroupID>,
+    ) -> Self {
+        let mut edges = edges.collect::<Vec<_>>();
+        edges.sort_unstable();
+        Self {
+            config,
+            nodes,
+            edges,
+            groups,
+        }
+    }
+
+    fn group(&self, node: usize) -> GroupID {
+        self.groups.get(&node).copied().unwrap_or(node)
+    }
+
+    pub fn write<W: Write>(&self, format: ExportFormat, writer: W) -> io::Result<()> {
+        match format {
+            ExportFormat::Dot => self.write_dot(writer),
+            ExportFormat::GraphML => self.write_graphml(writer),
+            ExportFormat::Json => self.write_json(writer),
+            ExportFormat::Sqlite => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "sqlite is written from an index, not a graph export",
+            )),
+        }
+    }
+
+    /// graphviz DOT, edges are labeled with their hamming distance
+    pub fn write_dot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "graph similarity {{")?;
+        writeln!(
+            writer,
+            "    graph [hash_algorithm=\"{}\", hash_grid_size={}, hash_filter=\"{}\", hash_version=\"{}\"];",
+            self.config.algorithm.name(),
+            self.config.grid_size,
+            self.config.filter.name(),
+            escape_dot(&self.config.version)
+        )?;
+        for (idx, entry) in self.nodes.iter().enumerate() {
+            let aliases = match aliases(entry) {
+                Some(aliases) => format!(", aliases=\"{}\"", escape_dot(&aliases)),
+                None => String::new(),
+            };
+            writeln!(
+                writer,
+                "    {} [label=\"{}\", path=\"{}\", hash=\"{:016x}\", group={}{}];",
+                idx,
+                escape_dot(&file_name(entry)),
+                escape_dot(&entry.path.to_string_lossy()),
+                entry.hash,
+                self.group(idx),
+                aliases
+            )?;
+        }
+        for &(a, b, dist) in &self.edges {
+            writeln!(
+                writer,
+                "    {} -- {} [distance={}, label=\"{}\"];",
+                a, b, dist, dist
+            )?;
+        }
+        writeln!(writer, "}}")
+    }
+
+    /// GraphML, hashes are written as hex strings because GraphML's `long` is signed
+    pub fn write_graphml<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            writer,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        for key in ["hash_algorithm", "hash_filter", "hash_version"] {
+            writeln!(
+                writer,
+                r#"  <key id="{}" for="graph" attr.name="{}" attr.type="string"/>"#,
+                key, key
+            )?;
+        }
+        writeln!(
+            writer,
+            r#"  <key id="hash_grid_size" for="graph" attr.name="hash_grid_size" attr.type="int"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="path" for="node" attr.name="path" attr.type="string"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="aliases" for="node" attr.name="aliases" attr.type="string"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="hash" for="node" attr.name="hash" attr.type="string"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="group" for="node" attr.name="group" attr.type="long"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <key id="distance" for="edge" attr.name="distance" attr.type="long"/>"#
+        )?;
+        writeln!(
+            writer,
+            r#"  <graph id="similarity" edgedefault="undirected">"#
+        )?;
+        writeln!(
+            writer,
+            r#"    <data key="hash_algorithm">{}</data>"#,
+            self.config.algorithm.name()
+        )?;
+        writeln!(
+            writer,
+            r#"    <data key="hash_grid_size">{}</data>"#,
+            self.config.grid_size
+        )?;
+        writeln!(
+            writer,
+            r#"    <data key="hash_filter">{}</data>"#,
+            self.config.filter.name()
+        )?;
+        writeln!(
+            writer,
+            r#"    <data key="hash_version">{}</data>"#,
+            escape_xml(&self.config.version)
+        )?;
+        for (idx, entry) in self.nodes.iter().enumerate() {
+            writeln!(writer, r#"    <node id="n{}">"#, idx)?;
+            writeln!(
+                writer,
+                r#"      <data key="path">{}</data>"#,
+                escape_xml(&entry.path.to_string_lossy())
+            )?;
+            if let Some(aliases) = aliases(entry) {
+                writeln!(
+                    writer,
+                    r#"      <data key="aliases">{}</data>"#,
+                    escape_xml(&aliases)
+                )?;
+            }
+            writeln!(
+                writer,
+                r#"      <data key="hash">{:016x}</data>"#,
+                entry.hash
+            )?;
+            writeln!(
+                writer,
+                r#"      <data key="group">{}</data>"#,
+                self.group(idx)
+            )?;
+            writeln!(writer, r#"    </node>"#)?;
+        }
+        for &(a, b, dist) in &self.edges {
+            writeln!(writer, r#"    <edge source="n{}" target="n{}">"#, a, b)?;
+            writeln!(writer, r#"      <data key="distance">{}</data>"#, dist)?;
+            writeln!(writer, r#"    </edge>"#)?;
+        }
+        writeln!(writer, "  </graph>")?;
+        writeln!(writer, "</graphml>")
+    }
+
+    /// node-link JSON, the same layout networkx and d3 use
+    pub fn write_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        #[derive(Serialize)]
+        struct Node<'a> {
+            id: usize,
+            path: &'a Path,
+            #[serde(skip_serializing_if = "<[_]>::is_empty")]
+            aliases: &'a [PathBuf],
+            #[serde(with = "hexhash")]
+            hash: u64,
+            group: GroupID,
+        }
+        #[derive(Serialize)]
+        struct Link {
+            source: usize,
+            target: usize,
+            distance: u64,
+        }
+        #[derive(Serialize)]
+        struct GraphAttributes<'a> {
+            schema_version: u32,
+            hash_config: &'a HashConfig,
+        }
+        #[derive(Serialize)]
+        struct NodeLink<'a> {
+            directed: bool,
+            multigraph: bool,
+            graph: GraphAttributes<'a>,
+            nodes: Vec<Node<'a>>,
+            links: Vec<Link>,
+        }
+
+        let node_link = NodeLink {
+            directed: false,
+            multigraph: false,
+            graph: GraphAttributes {
+                schema_version: NODE_LINK_VERSION,
+                hash_config: self.config,
+            },
+            nodes: self
+                .nodes
+                .iter()
+                .enumerate()
+                .map(|(id, entry)| Node {
+                    id,
+                    path: &entry.path,
+                    aliases: &entry.aliases,
+                    hash: entry.hash,
+                    group: self.group(id),
+                })
+                .collect(),
+            links: self
+                .edges
+                .iter()
+                .map(|&(source, target, distance)| Link {
+                    source,
+                    target,
+                    distance,
+                })
+                .collect(),
+        };
+        serde_json::to_writer_pretty(writer, &node_link)?;
+        Ok(())
+    }
+}
+
+fn file_name(entry: &ImageEntry) -> String {
+    entry
+        .path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// the other paths of a node one per line, `None` when it has none
+fn aliases(entry: &ImageEntry) -> Option<String> {
+    let aliases = entry
+        .aliases
+        .iter()
+        .map(|alias| alias.to_string_lossy())
+        .collect::<Vec<_>>();
+    (!aliases.is_empty()).then(|| aliases.join("\n"))
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[test]
+fn export_escapes_paths() {
+    let nodes = vec![
+        ImageEntry {
+            hash: u64::MAX,
+            path: PathBuf::from("a \"quoted\" <name>.png"),
+            aliases: vec![],
+        },
+        ImageEntry {
+            hash: 0,
+            path: PathBuf::from("b.png"),
+            aliases: vec![PathBuf::from("link/b.png"), PathBuf::from("copy of b.png")],
+        },
+    ];
+    let groups = HashMap::new();
+    let config = HashConfig::default();
+    let export = GraphExport::new(&config, &nodes, vec![(0, 1, 100)].into_iter(), &groups);
+
+    let mut dot = vec![];
+    export.write(ExportFormat::Dot, &mut dot).unwrap();
+    let dot = String::from_utf8(dot).unwrap();
+    assert!(dot.contains(r#"path="a \"quoted\" <name>.png", hash="ffffffffffffffff""#));
+    assert!(dot.contains("0 -- 1 [distance=100"));
+    assert!(dot.contains(r#"group=1, aliases="link/b.png\ncopy of b.png"];"#));
+
+    let mut graphml = vec![];
+    export.write(ExportFormat::GraphML, &mut graphml).unwrap();
+    let graphml = String::from_utf8(graphml).unwrap();
+    assert!(graphml.contains("a &quot;quoted&quot; &lt;name&gt;.png"));
+    assert!(graphml.contains("<data key=\"aliases\">link/b.png\ncopy of b.png</data>"));
+
+    let mut json = vec![];
+    export.write(ExportFormat::Json, &mut json).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    assert_eq!(json["links"][0]["distance"], 100);
+    assert_eq!(json["nodes"][1]["group"], 1);
+    assert_eq!(json["graph"]["hash_config"]["algorithm"], "ahash");
+    assert_eq!(json["nodes"][0]["hash"], "ffffffffffffffff");
+    assert!(json["nodes"][0].get("aliases").is_none());
+    assert_eq!(json["nodes"][1]["aliases"][1], "copy of b.png");
+}
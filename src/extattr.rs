@@ -0,0 +1,67 @@
+//! hashes stored in a `user.` extended attribute of the image itself, so they follow the file across renames and moves
+use super::*;
+use crate::{cache, hexhash, perceptual::HashConfig};
+use std::{fs::Metadata, io};
+
+/// name of the extended attribute holding the hash
+pub const ATTRIBUTE: &str = "user.image_grouper.hash";
+
+/// # Description
+/// the attribute value: `<config tag> <hex hash> <size> <mtime seconds>.<mtime nanoseconds>`
+/// ## Comments
+/// size and mtime are recorded because rewriting a file in place keeps its attributes
+fn value(metadata: &Metadata, config: &HashConfig, hash: u64) -> Option<String> {
+    let (secs, nanos) = cache::mtime(metadata)?;
+    Some(format!(
+        "{} {} {} {}.{:09}",
+        config.tag(),
+        hexhash::to_hex(hash),
+        metadata.len(),
+        secs,
+        nanos
+    ))
+}
+
+/// # Description
+/// hash stored on `path`
+/// ## returns
+/// `None` if there is no attribute, it was made with another config or the file changed since
+pub fn read(path: &Path, metadata: &Metadata, config: &HashConfig) -> Option<u64> {
+    let stored = xattr::get(path, ATTRIBUTE).ok()??;
+    let stored = String::from_utf8(stored).ok()?;
+    let (tag, rest) = stored.split_once(' ')?;
+    let (hash, _) = rest.split_once(' ')?;
+    let hash = hexhash::from_hex(hash)?;
+    let expected = value(metadata, config, hash)?;
+    (HashConfig::from_tag(tag)? == *config && stored == expected).then_some(hash)
+}
+
+/// stores `hash` on `path`, fails on filesystems without user extended attributes
+pub fn write(path: &Path, metadata: &Metadata, config: &HashConfig, hash: u64) -> io::Result<()> {
+    let value = value(metadata, config, hash).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::Unsupported, "file has no modification time")
+    })?;
+    xattr::set(path, ATTRIBUTE, value.as_bytes())
+}
+
+#[test]
+fn xattr_round_trip() {
+    let dir = TempDir::new("xattr");
+    let path = dir.join("image.png");
+    std::fs::write(&path, b"not really an image").unwrap();
+    let metadata = std::fs::metadata(&path).unwrap();
+    let config = HashConfig::new(HashType::AHASH);
+    if write(&path, &metadata, &config, 0x0123_4567_89ab_cdef).is_err() {
+        // temp dir without user xattr support
+        return;
+    }
+    assert_eq!(read(&path, &metadata, &config), Some(0x0123_4567_89ab_cdef));
+    assert_eq!(
+        read(&path, &metadata, &HashConfig::new(HashType::DHASH)),
+        None
+    );
+
+    std::fs::write(&path, b"rewritten in place, attributes stay").unwrap();
+    let metadata = std::fs::metadata(&path).unwrap();
+    assert_eq!(read(&path, &metadata, &config), None);
+}
@@ -1,73 +1,470 @@
+use crate::interrupt;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use rayon::prelude::*;
 use std::{
-    collections::VecDeque,
-    fs, io,
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::OsStr,
+    fs,
+    io::{self, Read},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
 
+/// ignore files honoured in every directory when `ScanOptions::ignore_files` is set, later ones win
+pub const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+/// # Description
+/// what a `FileSystemIterator` descends into
+/// ## Comments
+/// - `excluded` directories are matched by device and inode, so any path spelling of them is skipped
+/// - globs are matched against the path relative to the starting directory and against the bare file name,
+///   so `@eaDir` skips that folder anywhere while `exports/**` only skips it at the top
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// follow symlinks below the starting directories, the starting directories themselves are always followed
+    pub follow_symlinks: bool,
+    pub excluded: Vec<PathBuf>,
+    /// when not empty files have to match one of these, directories are always entered
+    pub include: GlobSet,
+    /// matching files and directories are skipped
+    pub exclude: GlobSet,
+    /// deepest level yielded, 1 is only the entries of the starting directory
+    pub max_depth: Option<usize>,
+    /// yield and enter entries whose name starts with a dot
+    pub hidden: bool,
+    /// skip whatever the `.gitignore`/`.ignore` files found on the way ignore
+    pub ignore_files: bool,
+}
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            follow_symlinks: true,
+            excluded: vec![],
+            include: GlobSet::empty(),
+            exclude: GlobSet::empty(),
+            max_depth: None,
+            hidden: true,
+            ignore_files: true,
+        }
+    }
+}
+
+/// compiles glob patterns for `ScanOptions::include` and `ScanOptions::exclude`
+pub fn glob_set<'a>(
+    patterns: impl IntoIterator<Item = &'a str>,
+) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build()
+}
+
+/// (device, inode) of a file or directory, the same pair means the same file however it was reached
+pub type FileId = (u64, u64);
+
+fn file_id(metadata: &fs::Metadata) -> FileId {
+    (metadata.dev(), metadata.ino())
+}
+
+/// ignore files of a directory and all of its ancestors up to the starting directory, deepest last
+type IgnoreStack = Arc<Vec<Gitignore>>;
+
+/// # Description
+/// the ignore stack for the children of `dir`
+/// ## returns
+/// `parent` itself when `dir` has no readable ignore files
+fn push_ignores(parent: &IgnoreStack, dir: &Path) -> IgnoreStack {
+    let mut builder = GitignoreBuilder::new(dir);
+    let mut found = false;
+    for name in IGNORE_FILES {
+        let file = dir.join(name);
+        if file.is_file() {
+            found |= builder.add(file).is_none();
+        }
+    }
+    match builder.build() {
+        Ok(ignores) if found => {
+            let mut stack = parent.as_ref().clone();
+            stack.push(ignores);
+            Arc::new(stack)
+        }
+        _ => parent.clone(),
+    }
+}
+
+/// the deepest ignore file with an opinion about `path` decides
+fn is_ignored(stack: &IgnoreStack, path: &Path, is_dir: bool) -> bool {
+    stack
+        .iter()
+        .rev()
+        .map(|ignores| ignores.matched(path, is_dir))
+        .find(|matched| !matched.is_none())
+        .is_some_and(|matched| matched.is_ignore())
+}
+
+/// a path waiting to be visited along with its depth below the starting directory
+/// and the ignore files that apply to it
+type Pending = (PathBuf, usize, IgnoreStack);
+
+/// the per path decisions shared by `FileSystemIterator` and `walk_parallel`
+struct Scanner {
+    root: PathBuf,
+    options: ScanOptions,
+    /// directories in `ScanOptions::excluded`
+    excluded: HashSet<FileId>,
+}
+impl Scanner {
+    fn new(root: &Path, options: &ScanOptions) -> Self {
+        let excluded = options
+            .excluded
+            .iter()
+            .filter_map(|dir| fs::metadata(dir).ok())
+            .filter(|metadata| metadata.is_dir())
+            .map(|metadata| file_id(&metadata))
+            .collect();
+        Self {
+            root: root.to_path_buf(),
+            options: options.clone(),
+            excluded,
+        }
+    }
+
+    fn start(&self) -> Pending {
+        (self.root.clone(), 0, IgnoreStack::default())
+    }
+
+    /// whether the name based options let `path` through, `is_dir` is `None` before its metadata is known
+    fn is_wanted(&self, path: &Path, is_dir: Option<bool>) -> bool {
+        let options = &self.options;
+        let name = path.file_name().unwrap_or_default();
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let matches = |globs: &GlobSet| globs.is_match(relative) || globs.is_match(name);
+        match is_dir {
+            None => {
+                (options.hidden || !name.to_string_lossy().starts_with('.'))
+                    && !matches(&options.exclude)
+            }
+            Some(is_dir) => is_dir || options.include.is_empty() || matches(&options.include),
+        }
+    }
+
+    /// # Description
+    /// looks `pending` up and applies every filter to it
+    /// ## returns
+    /// `None` if it is skipped, otherwise its id and whether it is a directory
+    fn check(&self, (path, depth, ignores): &Pending) -> Option<(FileId, bool)> {
+        let depth = *depth;
+        if depth > 0 && !self.is_wanted(path, None) {
+            return None;
+        }
+        let metadata = if self.options.follow_symlinks || depth == 0 {
+            fs::metadata(path)
+        } else {
+            fs::symlink_metadata(path)
+        };
+        // broken symlinks and files that vanished since they were listed
+        let metadata = metadata.ok()?;
+        if metadata.file_type().is_symlink() {
+            return None;
+        }
+        let is_dir = metadata.is_dir();
+        if depth > 0 && (is_ignored(ignores, path, is_dir) || !self.is_wanted(path, Some(is_dir))) {
+            return None;
+        }
+        let id = file_id(&metadata);
+        if is_dir && self.excluded.contains(&id) {
+            return None;
+        }
+        Some((id, is_dir))
+    }
+
+    /// entries of a directory that passed `check`, nothing at the depth limit
+    fn children(&self, (path, depth, ignores): &Pending) -> Vec<Pending> {
+        if self.options.max_depth.is_some_and(|max| *depth >= max) {
+            return vec![];
+        }
+        let ignores = if self.options.ignore_files {
+            push_ignores(ignores, path)
+        } else {
+            ignores.clone()
+        };
+        match fs::read_dir(path) {
+            Ok(iterator) => iterator
+                .filter_map(|a| a.ok())
+                .map(|entry| (entry.path(), depth + 1, ignores.clone()))
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
 /// # Description
 /// iterative BFS traversal for objects in the filesystem
 /// ## Comments
 /// standard recursive(DFS) method of traversal would cause a stack overflow if the directory tree gets too deep
-/// so iterative method is preferred, especially for a CLI application
+/// so iterative method is preferred, especially for a CLI application.
+/// symlinks can make the tree a graph, so every directory is entered at most once
 pub struct FileSystemIterator {
-    path_queue: VecDeque<PathBuf>,
+    path_queue: VecDeque<Pending>,
+    scanner: Scanner,
+    visited: HashSet<FileId>,
 }
 impl FileSystemIterator {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self::with_options(path, &ScanOptions::default())
+    }
+
+    pub fn with_options<P: AsRef<Path>>(path: P, options: &ScanOptions) -> Self {
+        let scanner = Scanner::new(path.as_ref(), options);
         Self {
-            path_queue: vec![path.as_ref().to_path_buf()]
-                .into_iter()
-                .collect::<VecDeque<_>>(),
+            path_queue: vec![scanner.start()].into_iter().collect::<VecDeque<_>>(),
+            scanner,
+            visited: HashSet::new(),
         }
     }
 }
 impl Iterator for FileSystemIterator {
     type Item = PathBuf;
     fn next(&mut self) -> Option<Self::Item> {
-        self.path_queue.pop_front().map(|path| {
-            //add adjacent nodes to the queue
-            if let Ok(iterator) = fs::read_dir(&path) {
-                for entry in iterator.filter_map(|a| a.ok()) {
-                    let path = entry.path();
-                    self.path_queue.push_back(path);
+        while let Some(pending) = self.path_queue.pop_front() {
+            let (id, is_dir) = match self.scanner.check(&pending) {
+                Some(checked) => checked,
+                None => continue,
+            };
+            if is_dir {
+                if !self.visited.insert(id) {
+                    continue;
+                }
+                //add adjacent nodes to the queue
+                self.path_queue.extend(self.scanner.children(&pending));
+            }
+            return Some(pending.0);
+        }
+        None
+    }
+}
+
+/// # Description
+/// everything `FileSystemIterator::with_options` would yield, found with the directories listed and
+/// looked up in parallel
+/// ## Comments
+/// - meant for network filesystems where listing directories dominates the runtime
+/// - every directory is its own rayon task, idle threads steal the pending ones so a slow directory
+///   only holds up what is below it
+/// - stops listing once ctrl-c was pressed, what was found so far is returned
+/// - when symlinks make a directory reachable twice the shallowest, then lexicographically smallest path
+///   claims it, whichever task got there first. a walk through a path that lost its claim is cut short
+///   and what it found is dropped at the end, so the outcome doesn't depend on the scheduling
+/// ## returns
+/// the paths in sorted order, the same on every run over an unchanged tree
+pub fn walk_parallel<P: AsRef<Path>>(path: P, options: &ScanOptions) -> Vec<PathBuf> {
+    walk_parallel_with_ids(path, options)
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// `walk_parallel` along with the id every path was looked up with, so nothing has to be stat'ed twice
+pub fn walk_parallel_with_ids<P: AsRef<Path>>(
+    path: P,
+    options: &ScanOptions,
+) -> Vec<(PathBuf, FileId)> {
+    walk(path.as_ref(), options)
+        .into_iter()
+        .map(|(path, id, _)| (path, id))
+        .collect()
+}
+
+/// `walk_parallel_with_ids` without the directories
+pub fn walk_parallel_files<P: AsRef<Path>>(
+    path: P,
+    options: &ScanOptions,
+) -> Vec<(PathBuf, FileId)> {
+    walk(path.as_ref(), options)
+        .into_iter()
+        .filter(|(_, _, is_dir)| !is_dir)
+        .map(|(path, id, _)| (path, id))
+        .collect()
+}
+
+/// the walk behind `walk_parallel`, every path comes with its id and whether it is a directory
+fn walk(path: &Path, options: &ScanOptions) -> Vec<(PathBuf, FileId, bool)> {
+    let scanner = Scanner::new(path, options);
+    let walk = ParallelWalk {
+        scanner,
+        claims: Mutex::new(HashMap::new()),
+        found: Mutex::new(vec![]),
+    };
+    let start = walk.scanner.start();
+    if let Some((id, is_dir)) = walk.scanner.check(&start) {
+        if walk.claim(&start, id, is_dir) {
+            rayon::scope(|scope| walk.list(scope, start, id));
+        }
+    }
+    let ParallelWalk {
+        scanner,
+        claims,
+        found,
+    } = walk;
+    let claims = claims.into_inner().unwrap();
+    let mut found = found.into_inner().unwrap();
+    found.sort_unstable();
+    // parents sort before their children, so a path is only kept below a directory that was kept
+    let mut kept = HashSet::new();
+    found
+        .into_iter()
+        .filter(|(path, id, is_dir)| {
+            let below_kept =
+                *path == scanner.root || path.parent().is_some_and(|parent| kept.contains(parent));
+            if !below_kept {
+                return false;
+            }
+            if *is_dir {
+                if claims.get(id).map(|(_, claimed)| claimed) != Some(path) {
+                    return false;
                 }
+                kept.insert(path.clone());
             }
-            path
+            true
         })
+        .collect()
+}
+
+/// state shared by the tasks of `walk_parallel_with_ids`
+struct ParallelWalk {
+    scanner: Scanner,
+    /// the depth and path every directory was claimed with
+    claims: Mutex<HashMap<FileId, (usize, PathBuf)>>,
+    /// everything that passed `Scanner::check`, with paths of directories that lost their claim
+    found: Mutex<Vec<(PathBuf, FileId, bool)>>,
+}
+impl ParallelWalk {
+    /// # Description
+    /// records a checked path, directories are claimed unless a shallower or smaller path already has them
+    /// ## returns
+    /// whether it was recorded
+    fn claim(&self, (path, depth, _): &Pending, id: FileId, is_dir: bool) -> bool {
+        if is_dir {
+            let mut claims = self.claims.lock().unwrap();
+            if claims
+                .get(&id)
+                .is_some_and(|(claimed_depth, claimed)| (*claimed_depth, claimed) <= (*depth, path))
+            {
+                return false;
+            }
+            claims.insert(id, (*depth, path.clone()));
+        }
+        self.found.lock().unwrap().push((path.clone(), id, is_dir));
+        true
+    }
+
+    /// whether `path` still holds the claim on directory `id`
+    fn holds_claim(&self, path: &Path, id: FileId) -> bool {
+        self.claims
+            .lock()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|(_, claimed)| claimed == path)
     }
+
+    /// checks the entries of a claimed directory in parallel and spawns a task for every subdirectory
+    fn list<'s>(&'s self, scope: &rayon::Scope<'s>, pending: Pending, id: FileId) {
+        if interrupt::requested() || !self.holds_claim(&pending.0, id) {
+            return;
+        }
+        let children = self
+            .scanner
+            .children(&pending)
+            .into_par_iter()
+            .filter_map(|child| Some((self.scanner.check(&child)?, child)))
+            .collect::<Vec<_>>();
+        for ((id, is_dir), child) in children {
+            if self.claim(&child, id, is_dir) && is_dir {
+                scope.spawn(move |scope| self.list(scope, child, id));
+            }
+        }
+    }
+}
+
+/// # Description
+/// reads a list of paths, one per line or NUL separated as written by `find -print0`
+/// ## Comments
+/// the list counts as NUL separated as soon as it contains a NUL byte, paths can't contain one.
+/// empty entries and the `\r` of windows line endings are dropped
+pub fn read_path_list<R: Read>(mut reader: R) -> io::Result<Vec<PathBuf>> {
+    let mut list = vec![];
+    reader.read_to_end(&mut list)?;
+    let separator = if list.contains(&0) { 0 } else { b'\n' };
+    Ok(list
+        .split(|&byte| byte == separator)
+        .map(|entry| match entry {
+            [rest @ .., b'\r'] if separator == b'\n' => rest,
+            entry => entry,
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| PathBuf::from(OsStr::from_bytes(entry)))
+        .collect())
+}
+
+/// a file to hash along with the other paths it can be reached under
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputFile {
+    pub path: PathBuf,
+    pub aliases: Vec<PathBuf>,
+}
+
+/// # Description
+/// folds paths leading to the same file into one `InputFile`, so hardlinks, bind mounts and symlinks
+/// of an image are only decoded once
+/// ## Comments
+/// files are told apart by device and inode, the first path of a file in `files` becomes its main path and
+/// the order of first appearance is kept. only the paths that come without an id (e.g. from
+/// `walk_parallel_with_ids`) are stat'ed, the ones that can't be stay on their own
+pub fn merge_aliases(files: Vec<(PathBuf, Option<FileId>)>) -> Vec<InputFile> {
+    let ids = files
+        .par_iter()
+        .map(|(path, id)| id.or_else(|| fs::metadata(path).ok().map(|metadata| file_id(&metadata))))
+        .collect::<Vec<_>>();
+    let mut positions: HashMap<FileId, usize> = HashMap::new();
+    let mut merged: Vec<InputFile> = vec![];
+    for ((path, _), id) in files.into_iter().zip(ids) {
+        if let Some(&pos) = id.and_then(|id| positions.get(&id)) {
+            merged[pos].aliases.push(path);
+            continue;
+        }
+        if let Some(id) = id {
+            positions.insert(id, merged.len());
+        }
+        merged.push(InputFile {
+            path,
+            aliases: vec![],
+        });
+    }
+    merged
 }
 
 #[allow(dead_code)]
-pub fn collect_files_iterative<P, PRED>(path: P, predicate: PRED) -> Vec<PathBuf>
+pub fn collect_files_iterative<P, PRED>(
+    path: P,
+    options: &ScanOptions,
+    predicate: PRED,
+) -> Vec<PathBuf>
 where
     PRED: Fn(&PathBuf) -> bool,
     P: AsRef<Path>,
 {
-    //visited table not needed because filesystems have no cycles
-    let mut path_queue = VecDeque::new();
-    path_queue.push_back(path.as_ref().to_path_buf());
-    let mut file_list = vec![];
-
-    while let Some(path) = path_queue.pop_front() {
-        //add adjacent nodes to the queue
-        if let Ok(iterator) = fs::read_dir(&path) {
-            for entry in iterator.filter_map(|a| a.ok()) {
-                let path = entry.path();
-                path_queue.push_back(path);
-            }
-        }
-
-        //check if the file what were looking for before returning the path
-        if path.is_file() && predicate(&path) {
-            file_list.push(path);
-        }
-    }
-    file_list
+    FileSystemIterator::with_options(path, options)
+        .filter(|path| path.is_file() && predicate(path))
+        .collect()
 }
 
 #[allow(dead_code)]
 /// # Description
-/// this way of traversing the file is not efficent and will cause a stack overflow if file tree is too deep
+/// this way of traversing the file is not efficent and will cause a stack overflow if file tree is too deep,
+/// it also never ends on a symlink loop
 pub fn collect_files<P: AsRef<Path>>(path: P) -> io::Result<Vec<PathBuf>> {
     if path.as_ref().is_file() {
         return Ok(vec![path.as_ref().to_path_buf()]);
@@ -82,4 +479,187 @@ pub fn collect_files<P: AsRef<Path>>(path: P) -> io::Result<Vec<PathBuf>> {
         }
     }
     Ok(files)
-}
\ No newline at end of file
+}
+
+#[test]
+fn traversal_survives_symlink_loops() {
+    let root = crate::TempDir::new("walk");
+    fs::create_dir_all(root.join("photos/nested")).unwrap();
+    fs::create_dir_all(root.join("sorted")).unwrap();
+    fs::write(root.join("photos/nested/a.png"), b"").unwrap();
+    std::os::unix::fs::symlink(&root, root.join("photos/nested/loop")).unwrap();
+    std::os::unix::fs::symlink(root.join("photos/nested/a.png"), root.join("sorted/0.png"))
+        .unwrap();
+
+    let files = |options: &ScanOptions| {
+        let mut files = FileSystemIterator::with_options(&root, options)
+            .filter(|path| path.is_file())
+            .map(|path| path.strip_prefix(&root).unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    };
+    let followed = files(&ScanOptions::default());
+    assert_eq!(
+        followed,
+        [Path::new("photos/nested/a.png"), Path::new("sorted/0.png")]
+    );
+
+    let excluded = ScanOptions {
+        excluded: vec![root.join("photos/nested/loop/sorted")],
+        ..ScanOptions::default()
+    };
+    assert_eq!(files(&excluded), [Path::new("photos/nested/a.png")]);
+
+    let unfollowed = ScanOptions {
+        follow_symlinks: false,
+        ..ScanOptions::default()
+    };
+    assert_eq!(files(&unfollowed), [Path::new("photos/nested/a.png")]);
+}
+
+#[test]
+fn traversal_honours_filters() {
+    let root = crate::TempDir::new("filters");
+    for dir in [
+        "photos/@eaDir",
+        "photos/.thumbnails",
+        "exports",
+        "photos/deep/deeper",
+    ] {
+        fs::create_dir_all(root.join(dir)).unwrap();
+    }
+    for file in [
+        "top.png",
+        "notes.txt",
+        "photos/a.jpg",
+        "photos/@eaDir/a.jpg",
+        "photos/.thumbnails/a.png",
+        "photos/scratch.png",
+        "photos/deep/deeper/b.png",
+        "exports/c.png",
+    ] {
+        fs::write(root.join(file), b"").unwrap();
+    }
+    fs::write(root.join("photos/.gitignore"), "scratch.*\n").unwrap();
+
+    let files = |options: &ScanOptions| {
+        let mut files = collect_files_iterative(&root, options, |path| {
+            !path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with(".git")
+        })
+        .into_iter()
+        .map(|path| {
+            path.strip_prefix(&root)
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect::<Vec<_>>();
+        files.sort();
+        files
+    };
+    let options = ScanOptions {
+        include: glob_set(["*.png", "*.jpg"]).unwrap(),
+        exclude: glob_set(["@eaDir", "exports/**"]).unwrap(),
+        hidden: false,
+        ..ScanOptions::default()
+    };
+    assert_eq!(
+        files(&options),
+        ["photos/a.jpg", "photos/deep/deeper/b.png", "top.png"]
+    );
+
+    let shallow = ScanOptions {
+        max_depth: Some(2),
+        ignore_files: false,
+        ..options
+    };
+    assert_eq!(
+        files(&shallow),
+        ["photos/a.jpg", "photos/scratch.png", "top.png"]
+    );
+}
+
+#[test]
+fn parallel_walk_matches_iterator() {
+    let root = crate::TempDir::new("parallel");
+    for i in 0..8 {
+        fs::create_dir_all(root.join(format!("d{}/e{}", i, i % 3))).unwrap();
+        fs::write(root.join(format!("d{}/{}.png", i, i)), b"").unwrap();
+        fs::write(root.join(format!("d{}/e{}/{}.jpg", i, i % 3, i)), b"").unwrap();
+    }
+    std::os::unix::fs::symlink(root.join("d1"), root.join("d0/alias")).unwrap();
+    std::os::unix::fs::symlink(&root, root.join("d2/e2/loop")).unwrap();
+
+    let options = ScanOptions::default();
+    let mut sequential = FileSystemIterator::with_options(&root, &options).collect::<Vec<_>>();
+    sequential.sort();
+    let parallel = walk_parallel(&root, &options);
+    // d1 sits one level above d0/alias, so both walkers enter it through its own name
+    assert_eq!(parallel, sequential);
+    assert_eq!(parallel, walk_parallel(&root, &options));
+    assert_eq!(parallel.iter().filter(|path| path.is_file()).count(), 16);
+
+    // a task that reaches d1 through the alias first loses the claim to its own name
+    let walk = ParallelWalk {
+        scanner: Scanner::new(&root, &options),
+        claims: Mutex::new(HashMap::new()),
+        found: Mutex::new(vec![]),
+    };
+    let id = file_id(&fs::metadata(root.join("d1")).unwrap());
+    let alias = (root.join("d0/alias"), 2, IgnoreStack::default());
+    let own = (root.join("d1"), 1, IgnoreStack::default());
+    assert!(walk.claim(&alias, id, true));
+    assert!(walk.claim(&own, id, true));
+    assert!(!walk.claim(&alias, id, true));
+    assert!(!walk.holds_claim(&alias.0, id));
+}
+
+#[test]
+fn path_lists_split_on_nul_or_newline() {
+    let lines = read_path_list(&b"a.png\r\nwith space.jpg\n\nc.png"[..]).unwrap();
+    assert_eq!(
+        lines,
+        ["a.png", "with space.jpg", "c.png"].map(PathBuf::from)
+    );
+    let nul = read_path_list(&b"new\nline.png\0b.png\0"[..]).unwrap();
+    assert_eq!(nul, ["new\nline.png", "b.png"].map(PathBuf::from));
+}
+
+#[test]
+fn hardlinks_share_one_input() {
+    let root = crate::TempDir::new("aliases");
+    fs::create_dir_all(root.join("b")).unwrap();
+    fs::write(root.join("a.png"), b"a").unwrap();
+    fs::write(root.join("c.png"), b"c").unwrap();
+    fs::hard_link(root.join("a.png"), root.join("b").join("a.png")).unwrap();
+
+    let mut files = walk_parallel_with_ids(&root, &ScanOptions::default());
+    files.retain(|(path, _)| path.is_file());
+    let mut files = files
+        .into_iter()
+        .map(|(path, id)| (path, Some(id)))
+        .collect::<Vec<_>>();
+    // a listed path, looked up by `merge_aliases` itself
+    files.push((root.join(".").join("c.png"), None));
+    let merged = merge_aliases(files);
+    assert_eq!(
+        merged,
+        [
+            InputFile {
+                path: root.join("a.png"),
+                aliases: vec![root.join("b").join("a.png")],
+            },
+            InputFile {
+                path: root.join("c.png"),
+                aliases: vec![root.join(".").join("c.png")],
+            },
+        ]
+    );
+    let missing = merge_aliases(vec![(root.join("gone.png"), None); 2]);
+    assert_eq!(missing.len(), 2);
+}
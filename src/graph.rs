@@ -1,4 +1,5 @@
 use super::*;
+use crate::bktree::BKTree;
 use rayon::prelude::*;
 use std::{
     cmp::{Ordering, Reverse},
@@ -14,9 +15,12 @@ pub struct StackFrame {
     pub printed: bool,
 }
 
-/// explicit DFS as rust iterator 
+/// adjacency list where every neighbour is paired with the hamming distance of the connecting edge
+pub type WeightedAdjacency = HashMap<usize, Vec<(usize, u64)>>;
+
+/// explicit DFS as rust iterator
 pub struct MSTIterator<'a> {
-    graph: &'a HashMap<usize, Vec<usize>>,
+    graph: &'a WeightedAdjacency,
     visited: HashSet<usize>,
     stack: Vec<StackFrame>,
 }
@@ -24,12 +28,16 @@ impl<'a> MSTIterator<'a> {
     pub fn new(g: &'a HammingMST) -> Self {
         let graph = &g.graph;
         let root = g.root;
-        let stack = vec![StackFrame {
-            idx: root,
-            edge_idx: 0,
-            len: graph.get(&root).unwrap().len(),
-            printed: false,
-        }];
+        let stack = graph
+            .get(&root)
+            .map(|adj| StackFrame {
+                idx: root,
+                edge_idx: 0,
+                len: adj.len(),
+                printed: false,
+            })
+            .into_iter()
+            .collect();
         Self {
             graph,
             visited: HashSet::new(),
@@ -42,13 +50,13 @@ impl<'a> Iterator for MSTIterator<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         let stack = &mut self.stack;
         let visited = &mut self.visited;
-        let graph = &mut self.graph; 
+        let graph = &mut self.graph;
 
         let mut res = None;
         stack.pop().map(|sf| {
             let mut sf = sf;
 
-            if sf.printed == false {
+            if !sf.printed {
                 res = Some(sf.idx)
             }
 
@@ -68,9 +76,9 @@ impl<'a> Iterator for MSTIterator<'a> {
             let adj_node_children_len = adj_node_list.len();
 
             if edge_cursor < adj_node_children_len {
-                let idx = adj_node_list[edge_cursor];
+                let (idx, _) = adj_node_list[edge_cursor];
                 let len = graph.get(&idx).expect("node should exist").len();
-                if visited.contains(&idx) == false {
+                if !visited.contains(&idx) {
                     stack.push(StackFrame {
                         idx,
                         edge_idx: 0,
@@ -85,16 +93,16 @@ impl<'a> Iterator for MSTIterator<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 /// contructus a minimum spanning tree were hamming distance is minimized
 pub struct HammingMST {
-    pub graph: HashMap<usize, Vec<usize>>,
+    pub graph: WeightedAdjacency,
     root: usize,
 }
 impl HammingMST {
     //create minimum spanning tree with kruskals algorithm
     pub fn new(nodes: &[ImageEntry]) -> Option<Self> {
-        #[derive(Copy, Clone, Eq, Ord, Debug)]
+        #[derive(Copy, Clone, Eq, Debug)]
         pub struct Edge {
             a: usize,
             b: usize,
@@ -105,9 +113,14 @@ impl HammingMST {
                 self.cost.eq(&other.cost)
             }
         }
+        impl Ord for Edge {
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.cost.cmp(&other.cost)
+            }
+        }
         impl PartialOrd for Edge {
             fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-                self.cost.partial_cmp(&other.cost)
+                Some(self.cmp(other))
             }
         }
 
@@ -115,7 +128,7 @@ impl HammingMST {
             return None;
         }
 
-        let mut graph = HashMap::<usize, Vec<usize>>::with_capacity(nodes.len());
+        let mut graph = WeightedAdjacency::with_capacity(nodes.len());
         let mut edge_table = BinaryHeap::new();
         let mut disjoint_sets: Vec<HashSet<usize>> = vec![];
 
@@ -146,14 +159,15 @@ impl HammingMST {
             // println!("{:?} popped..", edge);
             let a = edge.a;
             let b = edge.b;
+            let cost = edge.cost;
 
             let a_in_mst = graph.contains_key(&a);
             let b_in_mst = graph.contains_key(&b);
 
             if a_in_mst != b_in_mst {
                 if a_in_mst {
-                    graph.get_mut(&a).unwrap().push(b);
-                    graph.insert(b, vec![a]);
+                    graph.get_mut(&a).unwrap().push((b, cost));
+                    graph.insert(b, vec![(a, cost)]);
 
                     disjoint_sets
                         .iter_mut()
@@ -161,8 +175,8 @@ impl HammingMST {
                         .expect("set should exist")
                         .insert(b);
                 } else {
-                    graph.get_mut(&b).unwrap().push(a);
-                    graph.insert(a, vec![b]);
+                    graph.get_mut(&b).unwrap().push((a, cost));
+                    graph.insert(a, vec![(b, cost)]);
 
                     disjoint_sets
                         .iter_mut()
@@ -170,9 +184,9 @@ impl HammingMST {
                         .expect("set should exist")
                         .insert(a);
                 }
-            } else if a_in_mst == false && b_in_mst == false {
-                graph.insert(a, vec![b]);
-                graph.insert(b, vec![a]);
+            } else if !a_in_mst && !b_in_mst {
+                graph.insert(a, vec![(b, cost)]);
+                graph.insert(b, vec![(a, cost)]);
 
                 disjoint_sets.push(HashSet::new());
                 disjoint_sets.last_mut().unwrap().insert(a);
@@ -189,7 +203,7 @@ impl HammingMST {
                 if let Some((set_idx_1, set_idx_2)) = res_1.zip(res_2) {
                     let union = disjoint_sets[set_idx_1]
                         .union(&disjoint_sets[set_idx_2])
-                        .map(|&a| a)
+                        .copied()
                         .collect::<HashSet<_>>();
 
                     if set_idx_1 > set_idx_2 {
@@ -205,11 +219,11 @@ impl HammingMST {
                     graph
                         .get_mut(&a)
                         .expect("both a and b should exist in graph")
-                        .push(b);
+                        .push((b, cost));
                     graph
                         .get_mut(&b)
                         .expect("both a and b should exist in graph")
-                        .push(a);
+                        .push((a, cost));
                 }
             }
         }
@@ -219,26 +233,9 @@ impl HammingMST {
 
     /// create minimum spanning tree with prims algorithm
     pub fn new_prims(nodes: &[ImageEntry]) -> Option<Self> {
-        #[derive(Eq, Ord)]
-        pub struct HeapKey {
-            a_idx: usize,
-            b_idx: usize,
-            dist: u64,
-        }
-        impl PartialEq for HeapKey {
-            fn eq(&self, other: &Self) -> bool {
-                self.dist.eq(&other.dist)
-            }
-        }
-        impl PartialOrd for HeapKey {
-            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-                self.dist.partial_cmp(&other.dist)
-            }
-        }
-
         let mut visited_list = Vec::with_capacity(nodes.len());
         let mut visited_table = HashSet::<usize>::with_capacity(nodes.len());
-        let mut graph = HashMap::new();
+        let mut graph = WeightedAdjacency::new();
 
         if nodes.len() <= 1 {
             return None;
@@ -257,7 +254,7 @@ impl HammingMST {
                     nodes
                         .iter()
                         .enumerate()
-                        .filter(|(adj_idx, _)| visited_table.contains(adj_idx) == false)
+                        .filter(|(adj_idx, _)| !visited_table.contains(adj_idx))
                         .map(move |(adj_idx, _)| (vidx, vhash, adj_idx, nodes[adj_idx].hash))
                 })
                 .map(|(vidx, vhash, adj_idx, adj_hash)| {
@@ -265,24 +262,232 @@ impl HammingMST {
                 })
                 .par_bridge()
                 .min_by_key(|&(_, _, dist)| dist);
-            if let Some((vidx, adj_idx, _dist)) = lowest_cost_edge {
+            if let Some((vidx, adj_idx, dist)) = lowest_cost_edge {
                 visited_list.push(adj_idx);
                 visited_table.insert(adj_idx);
-                graph.insert(adj_idx, vec![]);
-                graph.get_mut(&vidx).map(|adj_nodes| {
-                    adj_nodes.push(adj_idx);
-                });
+                graph.insert(adj_idx, vec![(vidx, dist)]);
+                if let Some(adj_nodes) = graph.get_mut(&vidx) {
+                    adj_nodes.push((adj_idx, dist));
+                }
                 nodes_left -= 1;
             }
         }
         Some(Self { graph, root: 0 })
     }
 
-    pub fn iter<'a>(&'a self)->MSTIterator<'a>{
-        MSTIterator::new(&self)
+    pub fn iter(&self) -> MSTIterator<'_> {
+        MSTIterator::new(self)
+    }
+
+    /// # Description
+    /// iterates every edge of the tree exactly once
+    /// ## returns
+    /// `(a, b, distance)` triples where `a < b`
+    pub fn edges(&self) -> impl Iterator<Item = (usize, usize, u64)> + '_ {
+        self.graph.iter().flat_map(|(&a, adj)| {
+            adj.iter()
+                .filter(move |&&(b, _)| a < b)
+                .map(move |&(b, dist)| (a, b, dist))
+        })
+    }
+
+    /// sum of the hamming distances of every edge in the tree
+    pub fn total_weight(&self) -> u64 {
+        self.edges().map(|(_, _, dist)| dist).sum()
     }
 
+    /// number of edges touching `node`, zero if `node` isn't in the tree
+    pub fn degree(&self, node: usize) -> usize {
+        self.neighbours(node).len()
+    }
 
+    /// # Description
+    /// adjacent nodes of `node`
+    /// ## returns
+    /// `(neighbour, distance)` pairs, empty if `node` isn't in the tree
+    pub fn neighbours(&self, node: usize) -> &[(usize, u64)] {
+        self.graph
+            .get(&node)
+            .map(|adj| adj.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// # Description
+    /// single-linkage grouping: every edge longer than `max_distance` is cut and each
+    /// remaining connected component becomes a group
+    /// ## returns
+    /// a table mapping every node of the tree to its group, groups are identified by their smallest node
+    pub fn groups(&self, max_distance: u64) -> HashMap<usize, GroupID> {
+        let mut group_table = HashMap::with_capacity(self.graph.len());
+        let mut roots = self.graph.keys().copied().collect::<Vec<_>>();
+        roots.sort_unstable();
+
+        let mut stack = vec![];
+        for root in roots {
+            if group_table.contains_key(&root) {
+                continue;
+            }
+            group_table.insert(root, root);
+            stack.push(root);
+            while let Some(node) = stack.pop() {
+                for &(adj, dist) in self.neighbours(node) {
+                    if dist <= max_distance && !group_table.contains_key(&adj) {
+                        group_table.insert(adj, root);
+                        stack.push(adj);
+                    }
+                }
+            }
+        }
+        group_table
+    }
+
+    /// # Description
+    /// adds `new_nodes` (indices into `nodes`) to the tree without rebuilding it from scratch
+    /// ## Comments
+    /// an edge between two old nodes that isn't in the old tree can never be in the new one,
+    /// so kruskals algorithm only has to consider the old tree plus the edges touching a new node
+    pub fn insert(&mut self, nodes: &[ImageEntry], new_nodes: &[usize]) {
+        let mut new_nodes = new_nodes
+            .iter()
+            .copied()
+            .filter(|node| !self.graph.contains_key(node))
+            .collect::<Vec<_>>();
+        new_nodes.sort_unstable();
+        new_nodes.dedup();
+        if new_nodes.is_empty() {
+            return;
+        }
+
+        let old_nodes = self.graph.keys().copied().collect::<Vec<_>>();
+        let distance = |a: usize, b: usize| {
+            (
+                a.min(b),
+                a.max(b),
+                perceptual::hamming_distance(nodes[a].hash, nodes[b].hash),
+            )
+        };
+
+        let mut candidates = self.edges().collect::<Vec<_>>();
+        candidates.par_extend(new_nodes.par_iter().enumerate().flat_map_iter(|(k, &a)| {
+            old_nodes
+                .iter()
+                .chain(&new_nodes[k + 1..])
+                .map(move |&b| distance(a, b))
+        }));
+        candidates.par_sort_unstable_by_key(|&(a, b, dist)| (dist, a, b));
+
+        if self.graph.is_empty() {
+            self.root = new_nodes[0];
+        }
+        for adj in self.graph.values_mut() {
+            adj.clear();
+        }
+        for &node in &new_nodes {
+            self.graph.insert(node, vec![]);
+        }
+        let mut sets = DisjointSet::default();
+        for (a, b, dist) in candidates {
+            if sets.union(a, b) {
+                self.add_edge(a, b, dist);
+            }
+        }
+    }
+
+    /// # Description
+    /// removes `node` from the tree and reconnects the pieces it leaves behind
+    /// ## Comments
+    /// the surviving tree edges stay optimal, so only the cheapest edges between the pieces are searched for.
+    /// `node` keeps its slot in `nodes`, indices of the other nodes don't change
+    pub fn remove(&mut self, nodes: &[ImageEntry], node: usize) {
+        let adj = match self.graph.remove(&node) {
+            Some(adj) => adj,
+            None => return,
+        };
+        for &(neighbour, _) in &adj {
+            if let Some(neighbour_adj) = self.graph.get_mut(&neighbour) {
+                neighbour_adj.retain(|&(n, _)| n != node);
+            }
+        }
+        if self.root == node {
+            self.root = self.graph.keys().copied().min().unwrap_or(0);
+        }
+        // removing a leaf can't disconnect the tree
+        if adj.len() <= 1 {
+            return;
+        }
+
+        let mut tree = BKTree::new();
+        for &idx in self.graph.keys() {
+            tree.insert(nodes[idx].hash, idx);
+        }
+
+        // boruvka: every piece except the largest looks for its cheapest edge to another piece
+        loop {
+            let components = self.groups(u64::MAX);
+            let mut sizes = HashMap::<GroupID, usize>::new();
+            for &group in components.values() {
+                *sizes.entry(group).or_default() += 1;
+            }
+            if sizes.len() <= 1 {
+                break;
+            }
+            let largest = sizes
+                .iter()
+                .max_by_key(|&(&group, &size)| (size, Reverse(group)))
+                .map(|(&group, _)| group);
+
+            let mut cheapest = components
+                .par_iter()
+                .filter(|&(_, group)| Some(*group) != largest)
+                .filter_map(|(&a, &group)| {
+                    tree.nearest(nodes[a].hash, 1, |b| components.get(&b) != Some(&group))
+                        .first()
+                        .map(|&(b, bits)| (group, (bits, a.min(b), a.max(b))))
+                })
+                .collect::<Vec<_>>();
+            cheapest.sort_unstable();
+            cheapest.dedup_by_key(|&mut (group, _)| group);
+
+            let mut edges = cheapest
+                .into_iter()
+                .map(|(_, edge)| edge)
+                .collect::<Vec<_>>();
+            edges.sort_unstable();
+
+            let mut sets = DisjointSet::default();
+            for (&idx, &group) in &components {
+                sets.union(idx, group);
+            }
+            for (_, a, b) in edges {
+                if sets.union(a, b) {
+                    let dist = perceptual::hamming_distance(nodes[a].hash, nodes[b].hash);
+                    self.add_edge(a, b, dist);
+                }
+            }
+        }
+    }
+
+    /// # Description
+    /// renames every node of the tree, used after entries were removed from the underlying list
+    /// ## Comments
+    /// `new_index[old]` must be `Some` for every node still in the tree
+    pub fn relabel(&mut self, new_index: &[Option<usize>]) {
+        let rename = |old: usize| new_index[old].expect("node in tree should survive relabeling");
+        self.graph = self
+            .graph
+            .drain()
+            .map(|(node, adj)| {
+                let adj = adj.into_iter().map(|(n, dist)| (rename(n), dist)).collect();
+                (rename(node), adj)
+            })
+            .collect();
+        self.root = new_index.get(self.root).copied().flatten().unwrap_or(0);
+    }
+
+    fn add_edge(&mut self, a: usize, b: usize, dist: u64) {
+        self.graph.entry(a).or_default().push((b, dist));
+        self.graph.entry(b).or_default().push((a, dist));
+    }
 
     /// do a dfs on the tree
     pub fn dfs_preorder_iterative<CB: FnMut(&Self, &StackFrame)>(&self, mut call_back: CB) {
@@ -302,7 +507,7 @@ impl HammingMST {
         while let Some(sf) = stack.pop() {
             let mut sf = sf;
 
-            if sf.printed == false {
+            if !sf.printed {
                 call_back(self, &sf);
             }
 
@@ -322,9 +527,9 @@ impl HammingMST {
             let adj_node_children_len = adj_node_list.len();
 
             if edge_cursor < adj_node_children_len {
-                let idx = adj_node_list[edge_cursor];
+                let (idx, _) = adj_node_list[edge_cursor];
                 let len = graph.get(&idx).expect("node should exist").len();
-                if visited.contains(&idx) == false {
+                if !visited.contains(&idx) {
                     stack.push(StackFrame {
                         idx,
                         edge_idx: 0,
@@ -337,42 +542,316 @@ impl HammingMST {
     }
 }
 
+/// inputs larger than this are answered through a `BKTree` instead of comparing every pair
+pub const EXACT_KNN_LIMIT: usize = 4096;
+
+#[derive(Debug)]
+/// # Description
+/// k-nearest-neighbour graph over a list of images
+/// ## Comments
+/// `neighbours[i]` holds the `(node, distance)` pairs closest to node `i` sorted by distance,
+/// ties are broken by the smaller node so results don't depend on how the graph was built
+pub struct KnnGraph {
+    pub k: usize,
+    pub neighbours: Vec<Vec<(usize, u64)>>,
+}
+impl KnnGraph {
+    /// compares every pair for small inputs and uses an index for large ones
+    pub fn new(nodes: &[ImageEntry], k: usize) -> Self {
+        if nodes.len() <= EXACT_KNN_LIMIT {
+            Self::new_exact(nodes, k)
+        } else {
+            Self::new_indexed(nodes, k)
+        }
+    }
+
+    /// brute force, O(n^2) but trivially parallel
+    pub fn new_exact(nodes: &[ImageEntry], k: usize) -> Self {
+        let neighbours = (0..nodes.len())
+            .into_par_iter()
+            .map(|a| {
+                let mut candidates = nodes
+                    .iter()
+                    .enumerate()
+                    .filter(|&(b, _)| b != a)
+                    .map(|(b, entry)| (perceptual::bit_distance(nodes[a].hash, entry.hash), b))
+                    .collect::<Vec<_>>();
+                if candidates.len() > k && k > 0 {
+                    candidates.select_nth_unstable(k - 1);
+                }
+                candidates.truncate(k);
+                candidates.sort_unstable();
+                candidates
+                    .into_iter()
+                    .map(|(_, b)| {
+                        (
+                            b,
+                            perceptual::hamming_distance(nodes[a].hash, nodes[b].hash),
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { k, neighbours }
+    }
+
+    /// answers every query through a `BKTree`, still exact
+    pub fn new_indexed(nodes: &[ImageEntry], k: usize) -> Self {
+        let tree = BKTree::from_entries(nodes);
+        let neighbours = (0..nodes.len())
+            .into_par_iter()
+            .map(|a| {
+                tree.nearest(nodes[a].hash, k, |b| b != a)
+                    .into_iter()
+                    .map(|(b, _)| {
+                        (
+                            b,
+                            perceptual::hamming_distance(nodes[a].hash, nodes[b].hash),
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+        Self { k, neighbours }
+    }
+
+    /// # Description
+    /// every undirected edge of the graph exactly once
+    /// ## returns
+    /// `(a, b, distance)` triples where `a < b`
+    pub fn edges(&self) -> impl Iterator<Item = (usize, usize, u64)> + '_ {
+        let mut seen = HashSet::new();
+        self.neighbours
+            .iter()
+            .enumerate()
+            .flat_map(|(a, adj)| adj.iter().map(move |&(b, dist)| (a.min(b), a.max(b), dist)))
+            .filter(move |&(a, b, _)| seen.insert((a, b)))
+    }
+}
+
+/// union-find over arbitrary node indices
+#[derive(Default)]
+struct DisjointSet {
+    parent: HashMap<usize, usize>,
+}
+impl DisjointSet {
+    fn find(&mut self, node: usize) -> usize {
+        let mut root = node;
+        while let Some(&parent) = self.parent.get(&root) {
+            if parent == root {
+                break;
+            }
+            root = parent;
+        }
+        // path compression
+        let mut cur = node;
+        while cur != root {
+            let next = self.parent.insert(cur, root).unwrap_or(root);
+            cur = next;
+        }
+        root
+    }
+
+    /// merges the sets of `a` and `b`, returns false if they were already the same set
+    fn union(&mut self, a: usize, b: usize) -> bool {
+        let root_a = self.find(a);
+        let root_b = self.find(b);
+        if root_a == root_b {
+            return false;
+        }
+        self.parent.insert(root_a, root_b);
+        self.parent.entry(root_b).or_insert(root_b);
+        true
+    }
+}
+
+/// # Description
+/// puts `node` into an existing tour right next to the node it is most similar to,
+/// on whichever side adds the least cost
+/// ## Comments
+/// used to place a few new images without rerunning `iteratively_improve_tour`
+pub fn splice_into_tour(circuit: &mut Vec<usize>, node: usize, nodes: &[ImageEntry]) {
+    let dist = |a: usize, b: usize| perceptual::hamming_distance(nodes[a].hash, nodes[b].hash);
+    let nearest = circuit
+        .iter()
+        .enumerate()
+        .min_by_key(|&(_, &other)| dist(node, other))
+        .map(|(pos, _)| pos);
+
+    let pos = match nearest {
+        Some(pos) => pos,
+        None => {
+            circuit.push(node);
+            return;
+        }
+    };
 
-pub fn iteratively_improve_tour(max_iterations:u64, max_time:u128, circuit:&mut Vec<usize>, nodes:&Vec<ImageEntry>){
-    let len = nodes.len(); 
+    // cost added by putting `node` between `circuit[i - 1]` and `circuit[i]`
+    let insertion_cost = |i: usize| {
+        let before = i.checked_sub(1).map(|j| circuit[j]);
+        let after = circuit.get(i).copied();
+        match (before, after) {
+            (Some(a), Some(b)) => (dist(a, node) + dist(node, b)).saturating_sub(dist(a, b)),
+            (Some(a), None) => dist(a, node),
+            (None, Some(b)) => dist(node, b),
+            (None, None) => 0,
+        }
+    };
+
+    let insert_at = if insertion_cost(pos) <= insertion_cost(pos + 1) {
+        pos
+    } else {
+        pos + 1
+    };
+    circuit.insert(insert_at, node);
+}
+
+pub fn iteratively_improve_tour(
+    max_iterations: u64,
+    max_time: u128,
+    circuit: &mut [usize],
+    nodes: &[ImageEntry],
+) {
+    let len = circuit.len();
+    if len < 2 {
+        return;
+    }
     let t0 = Instant::now();
-    let mut iterations = 0; 
-    
-    let calc_cost = |c:&Vec<usize>|->u64 {
-        let mut cost = 0; 
-        for i in 0..len-1 {
+    let mut iterations = 0;
+
+    let calc_cost = |c: &[usize]| -> u64 {
+        let mut cost = 0;
+        for i in 0..len - 1 {
             let hash_i = nodes[c[i]].hash;
-            let hash_j = nodes[c[i+1]].hash; 
-            cost+=perceptual::hamming_distance(hash_i,hash_j);
+            let hash_j = nodes[c[i + 1]].hash;
+            cost += perceptual::hamming_distance(hash_i, hash_j);
         }
         cost
     };
 
-    let mut cost = calc_cost(&circuit);
+    let mut cost = calc_cost(circuit);
 
-    let before = cost; 
-    
-    while t0.elapsed().as_millis() < max_time && iterations < max_iterations{
+    let before = cost;
+
+    while t0.elapsed().as_millis() < max_time
+        && iterations < max_iterations
+        && !interrupt::requested()
+    {
         let a = fastrand::usize(0..len);
         let b = fastrand::usize(0..len);
         if a != b {
             circuit.swap(a, b);
-            let new_cost = calc_cost(&circuit);
+            let new_cost = calc_cost(circuit);
             if new_cost < cost {
-               
                 cost = new_cost;
-            }else{
+            } else {
                 circuit.swap(a, b);
             }
         }
-        iterations+=1; 
+        iterations += 1;
     }
 
-    println!("iteratively improved by: [before = {}, after = {}]",before, cost);
+    println!(
+        "iteratively improved by: [before = {}, after = {}]",
+        before, cost
+    );
+}
+
+#[test]
+fn mst_edge_api() {
+    let nodes = [0u64, 0b1, 0b111, u64::MAX]
+        .iter()
+        .map(|&hash| ImageEntry {
+            hash,
+            path: PathBuf::new(),
+            aliases: vec![],
+        })
+        .collect::<Vec<_>>();
+    let expected_weight = perceptual::hamming_distance(0, 0b1)
+        + perceptual::hamming_distance(0b1, 0b111)
+        + perceptual::hamming_distance(0b111, u64::MAX);
+
+    for mst in [HammingMST::new(&nodes), HammingMST::new_prims(&nodes)] {
+        let mst = mst.unwrap();
+        assert_eq!(mst.edges().count(), nodes.len() - 1);
+        assert!(mst.edges().all(|(a, b, _)| a < b));
+        assert_eq!(mst.total_weight(), expected_weight);
+        assert_eq!(mst.degree(1), 2);
+        assert_eq!(mst.degree(3), 1);
+        assert_eq!(
+            mst.neighbours(3),
+            &[(2, perceptual::hamming_distance(0b111, u64::MAX))]
+        );
+        assert_eq!(mst.iter().flatten().count(), nodes.len());
+    }
+}
 
+#[test]
+fn knn_indexed_matches_exact() {
+    fastrand::seed(7);
+    let nodes = (0..300)
+        .map(|i| {
+            // a few clusters of nearby hashes plus exact duplicates
+            let center = [0u64, 0xffff_0000, u64::MAX][i % 3];
+            let noise = if i % 5 == 0 {
+                0
+            } else {
+                1 << fastrand::u32(0..12)
+            };
+            ImageEntry {
+                hash: center ^ noise,
+                path: PathBuf::new(),
+                aliases: vec![],
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let exact = KnnGraph::new_exact(&nodes, 5);
+    let indexed = KnnGraph::new_indexed(&nodes, 5);
+    assert_eq!(exact.neighbours, indexed.neighbours);
+    assert!(exact.neighbours.iter().all(|adj| adj.len() == 5));
+    assert!(exact.edges().all(|(a, b, _)| a < b));
+}
+
+#[test]
+fn mst_incremental_matches_rebuild() {
+    fastrand::seed(11);
+    let nodes = (0..60)
+        .map(|_| ImageEntry {
+            hash: fastrand::u64(..) & 0xffff_ffff,
+            path: PathBuf::new(),
+            aliases: vec![],
+        })
+        .collect::<Vec<_>>();
+    let full = HammingMST::new(&nodes).unwrap();
+
+    let mut mst = HammingMST::new(&nodes[..55]).unwrap();
+    mst.insert(&nodes, &[55, 56, 57, 58, 59]);
+    assert_eq!(mst.edges().count(), nodes.len() - 1);
+    assert_eq!(mst.total_weight(), full.total_weight());
+    assert_eq!(mst.iter().flatten().count(), nodes.len());
+
+    // remove the node with the most edges so the tree falls apart into several pieces
+    let hub = (0..nodes.len()).max_by_key(|&n| mst.degree(n)).unwrap();
+    mst.remove(&nodes, hub);
+    let rest = nodes
+        .iter()
+        .enumerate()
+        .filter(|&(idx, _)| idx != hub)
+        .map(|(_, entry)| ImageEntry {
+            hash: entry.hash,
+            path: PathBuf::new(),
+            aliases: vec![],
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(mst.edges().count(), nodes.len() - 2);
+    assert_eq!(
+        mst.total_weight(),
+        HammingMST::new(&rest).unwrap().total_weight()
+    );
+    assert_eq!(mst.iter().flatten().count(), nodes.len() - 1);
+
+    let mut circuit = mst.iter().flatten().collect::<Vec<_>>();
+    splice_into_tour(&mut circuit, hub, &nodes);
+    assert_eq!(circuit.len(), nodes.len());
 }
@@ -0,0 +1,66 @@
+//! serde helpers that write 64-bit hashes as fixed width hex strings
+//!
+//! json numbers above 2^53 get rounded by javascript and many json tools, so a hash is written as
+//! 16 lowercase hex digits instead. reading accepts both forms so files from older versions still load.
+//! use with `#[serde(with = "hexhash")]`
+use serde::{
+    de::{self, Visitor},
+    Deserializer, Serializer,
+};
+use std::fmt;
+
+pub fn to_hex(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+/// parses exactly 16 hex digits, upper or lower case
+pub fn from_hex(text: &str) -> Option<u64> {
+    if text.len() != 16 {
+        return None;
+    }
+    u64::from_str_radix(text, 16).ok()
+}
+
+pub fn serialize<S: Serializer>(hash: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&to_hex(*hash))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    deserializer.deserialize_any(HashVisitor)
+}
+
+struct HashVisitor;
+impl<'de> Visitor<'de> for HashVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a hash as 16 hex digits or an unsigned integer")
+    }
+
+    fn visit_u64<E: de::Error>(self, hash: u64) -> Result<u64, E> {
+        Ok(hash)
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> Result<u64, E> {
+        from_hex(text).ok_or_else(|| E::invalid_value(de::Unexpected::Str(text), &self))
+    }
+}
+
+#[test]
+fn hex_hash_round_trip() {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Wrapper {
+        #[serde(with = "self")]
+        hash: u64,
+    }
+    let json = serde_json::to_string(&Wrapper {
+        hash: 16195086653305242567,
+    })
+    .unwrap();
+    assert_eq!(json, r#"{"hash":"e0c0818184edc3c7"}"#);
+    let parsed: Wrapper = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.hash, 16195086653305242567);
+    let legacy: Wrapper = serde_json::from_str(r#"{"hash":16195086653305242567}"#).unwrap();
+    assert_eq!(legacy.hash, 16195086653305242567);
+    assert!(serde_json::from_str::<Wrapper>(r#"{"hash":"123"}"#).is_err());
+}
@@ -0,0 +1,137 @@
+//! telling images apart by their contents, extensions lie and are often missing or upper case
+use super::*;
+use crate::archive;
+use image::{io::Reader as ImageReader, DynamicImage, ImageFormat, ImageResult};
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{Cursor, Read},
+};
+
+/// enough leading bytes for every signature `image::guess_format` knows
+const SNIFF_LEN: usize = 32;
+
+/// whether `path` ends in one of `VALID_IMAGE_EXTS`, ignoring case
+pub fn has_image_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            VALID_IMAGE_EXTS
+                .iter()
+                .any(|valid| valid.eq_ignore_ascii_case(ext))
+        })
+}
+
+/// # Description
+/// format of the file at `path` judged by its first bytes
+/// ## returns
+/// `None` for contents `image` doesn't recognize and for unreadable files
+pub fn sniff(path: &Path) -> Option<ImageFormat> {
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)
+        .ok()?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut header)
+        .ok()?;
+    sniff_bytes(&header)
+}
+
+/// format of an image held in memory judged by its first bytes
+pub fn sniff_bytes(bytes: &[u8]) -> Option<ImageFormat> {
+    image::guess_format(bytes).ok()
+}
+
+/// extension of `path`, or the usual one of its sniffed format when it has none
+pub fn extension(path: &Path) -> Option<OsString> {
+    match path.extension() {
+        Some(ext) => Some(ext.to_os_string()),
+        None => Some(sniff(path)?.extensions_str().first()?.into()),
+    }
+}
+
+/// extension of `name`, or the usual one of the format of `bytes` when it has none
+pub fn extension_for_bytes(name: &Path, bytes: &[u8]) -> Option<OsString> {
+    match name.extension() {
+        Some(ext) => Some(ext.to_os_string()),
+        None => Some(sniff_bytes(bytes)?.extensions_str().first()?.into()),
+    }
+}
+
+/// # Description
+/// whether `path` is worth decoding
+/// ## Comments
+/// the extension still counts because some formats (tga) have no signature to sniff
+pub fn looks_like_image(path: &Path) -> bool {
+    has_image_extension(path) || sniff(path).is_some()
+}
+
+/// # Description
+/// a reader over an image held in memory
+/// ## Comments
+/// the format comes from the contents, the extension of `name` is only a fallback just like for files
+fn memory_reader(name: &Path, bytes: Vec<u8>) -> ImageResult<ImageReader<Cursor<Vec<u8>>>> {
+    let mut reader = ImageReader::new(Cursor::new(bytes));
+    if let Ok(format) = ImageFormat::from_path(name) {
+        reader.set_format(format);
+    }
+    reader.with_guessed_format().map_err(Into::into)
+}
+
+/// the archive and member `path` points into, unless a real file by that name exists
+fn member_of(path: &Path) -> Option<(&Path, &str)> {
+    archive::split_member_path(path).filter(|_| !path.exists())
+}
+
+/// decodes `path` in the format its contents say, the extension is only a fallback.
+/// `archive!/inner` paths are read straight out of the archive
+pub fn open(path: &Path) -> ImageResult<DynamicImage> {
+    match member_of(path) {
+        Some((archive, inner)) => decode(Path::new(inner), archive::read_member(archive, inner)?),
+        None => ImageReader::open(path)?.with_guessed_format()?.decode(),
+    }
+}
+
+/// `open` for an image that is already in memory, `name` is only used for its extension
+pub fn decode(name: &Path, bytes: Vec<u8>) -> ImageResult<DynamicImage> {
+    memory_reader(name, bytes)?.decode()
+}
+
+/// width and height of the image at `path` without decoding all of it
+pub fn dimensions(path: &Path) -> ImageResult<(u32, u32)> {
+    match member_of(path) {
+        Some((archive, inner)) => {
+            memory_reader(Path::new(inner), archive::read_member(archive, inner)?)?
+                .into_dimensions()
+        }
+        None => ImageReader::open(path)?
+            .with_guessed_format()?
+            .into_dimensions(),
+    }
+}
+
+#[test]
+fn contents_beat_extensions() {
+    let dir = TempDir::new("sniff");
+    let png = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/test_set/a.png")).unwrap();
+    let upper = dir.join("IMG_0001.PNG");
+    let extensionless = dir.join("IMG_0002");
+    let mislabeled = dir.join("IMG_0003.jpg");
+    let broken = dir.join("IMG_0004.png");
+    let text = dir.join("notes.txt");
+    for path in [&upper, &extensionless, &mislabeled] {
+        std::fs::write(path, &png).unwrap();
+    }
+    std::fs::write(&broken, &png[..png.len() / 2]).unwrap();
+    std::fs::write(&text, b"not an image").unwrap();
+
+    assert!(has_image_extension(&upper));
+    assert!(!has_image_extension(&extensionless));
+    assert_eq!(sniff(&mislabeled), Some(ImageFormat::Png));
+    for path in [&upper, &extensionless, &mislabeled] {
+        assert!(looks_like_image(path));
+        assert_eq!(dimensions(path).unwrap(), (443, 443));
+    }
+    assert!(looks_like_image(&broken) && open(&broken).is_err());
+    assert_eq!(extension(&extensionless), Some("png".into()));
+    assert!(!looks_like_image(&text));
+}
@@ -0,0 +1,235 @@
+//! everything a run produces (hashes, spanning tree, tour and groups) in one file, so it can be
+//! queried, regrouped or turned back into folders without touching the images again
+use super::*;
+use crate::{
+    graph::{self, HammingMST},
+    perceptual::HashConfig,
+};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
+};
+
+/// bumped whenever the layout of `ImageIndex` changes
+/// ## Comments
+/// - 1: hashes written as json numbers
+/// - 2: hashes written as 16 hex digit strings
+pub const INDEX_VERSION: u32 = 2;
+/// oldest version `ImageIndex::load` still understands
+pub const MIN_INDEX_VERSION: u32 = 1;
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ImageIndex {
+    pub version: u32,
+    pub config: HashConfig,
+    /// images closer than this share a group
+    pub group_threshold: u64,
+    pub image_info_list: Vec<ImageEntry>,
+    /// `None` when there are fewer than two images
+    pub mst: Option<HammingMST>,
+    /// output order, indices into `image_info_list`
+    pub circuit: Vec<usize>,
+    pub group_table: HashMap<GroupID, GroupInfo>,
+}
+impl ImageIndex {
+    /// # Description
+    /// builds the spanning tree, tour and groups from scratch
+    /// ## Comments
+    /// spends up to `tour_time` milliseconds improving the tour
+    pub fn build(
+        config: HashConfig,
+        image_info_list: Vec<ImageEntry>,
+        group_threshold: u64,
+        tour_time: u128,
+    ) -> Self {
+        let mst = HammingMST::new(&image_info_list);
+        let mut circuit = match &mst {
+            Some(mst) => mst.iter().flatten().collect::<Vec<_>>(),
+            None => (0..image_info_list.len()).collect(),
+        };
+        graph::iteratively_improve_tour(30_000_000, tour_time, &mut circuit, &image_info_list);
+
+        let mut index = Self {
+            version: INDEX_VERSION,
+            config,
+            group_threshold,
+            image_info_list,
+            mst,
+            circuit,
+            group_table: HashMap::new(),
+        };
+        index.regroup(group_threshold);
+        index
+    }
+
+    /// recomputes `group_table` from the spanning tree
+    pub fn regroup(&mut self, group_threshold: u64) {
+        self.group_threshold = group_threshold;
+        let groups = match &self.mst {
+            Some(mst) => mst.groups(group_threshold),
+            None => (0..self.image_info_list.len())
+                .map(|idx| (idx, idx))
+                .collect(),
+        };
+        self.group_table = build_group_table(&self.image_info_list, &groups);
+    }
+
+    /// # Description
+    /// brings the index up to date with a fresh listing of the images
+    /// ## Comments
+    /// images whose path disappeared or whose hash changed are removed, new ones are inserted into the
+    /// spanning tree and spliced into the tour next to their nearest neighbour, so the expensive tour
+    /// optimization of `build` isn't repeated. images that stay pick up their current aliases
+    /// ## returns
+    /// (number of added images, number of removed images)
+    pub fn update(&mut self, current: Vec<ImageEntry>) -> (usize, usize) {
+        let current_table = current
+            .iter()
+            .map(|entry| (entry.path.as_path(), entry))
+            .collect::<HashMap<_, _>>();
+        let removed = self
+            .image_info_list
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                current_table
+                    .get(entry.path.as_path())
+                    .map(|current| current.hash)
+                    != Some(entry.hash)
+            })
+            .map(|(idx, _)| idx)
+            .collect::<HashSet<_>>();
+        for (idx, entry) in self.image_info_list.iter_mut().enumerate() {
+            if !removed.contains(&idx) {
+                entry.aliases = current_table[entry.path.as_path()].aliases.clone();
+            }
+        }
+
+        if let Some(mst) = &mut self.mst {
+            for &idx in &removed {
+                mst.remove(&self.image_info_list, idx);
+            }
+        }
+        self.circuit.retain(|idx| !removed.contains(idx));
+
+        // compact the list so indices stay dense
+        let mut new_index = vec![None; self.image_info_list.len()];
+        let mut kept = 0;
+        for (idx, slot) in new_index.iter_mut().enumerate() {
+            if !removed.contains(&idx) {
+                *slot = Some(kept);
+                kept += 1;
+            }
+        }
+        self.image_info_list = std::mem::take(&mut self.image_info_list)
+            .into_iter()
+            .enumerate()
+            .filter(|(idx, _)| !removed.contains(idx))
+            .map(|(_, entry)| entry)
+            .collect();
+        if let Some(mst) = &mut self.mst {
+            mst.relabel(&new_index);
+        }
+        for idx in self.circuit.iter_mut() {
+            *idx = new_index[*idx].expect("removed nodes were dropped from the circuit");
+        }
+
+        let known = self
+            .image_info_list
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect::<HashSet<_>>();
+        let first_new = self.image_info_list.len();
+        self.image_info_list.extend(
+            current
+                .into_iter()
+                .filter(|entry| !known.contains(&entry.path)),
+        );
+        let added = (first_new..self.image_info_list.len()).collect::<Vec<_>>();
+
+        match &mut self.mst {
+            Some(mst) if !mst.graph.is_empty() => mst.insert(&self.image_info_list, &added),
+            _ => self.mst = HammingMST::new(&self.image_info_list),
+        }
+        for &idx in &added {
+            graph::splice_into_tour(&mut self.circuit, idx, &self.image_info_list);
+        }
+        self.regroup(self.group_threshold);
+
+        (added.len(), removed.len())
+    }
+
+    /// writes the index as json, through a temporary file so an existing index is never left half written
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let temp_path = path.with_extension("tmp");
+        let writer = BufWriter::new(File::create(&temp_path)?);
+        serde_json::to_writer(writer, self)?;
+        fs::rename(&temp_path, path)
+    }
+
+    /// # Description
+    /// reads an index written by `save`
+    /// ## returns
+    /// an `InvalidData` error for indices written by an incompatible version of the format
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut index: Self = serde_json::from_reader(reader)?;
+        if !(MIN_INDEX_VERSION..=INDEX_VERSION).contains(&index.version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "index format version {} is not supported, expected {} to {}",
+                    index.version, MIN_INDEX_VERSION, INDEX_VERSION
+                ),
+            ));
+        }
+        // older layouts only differ in how hashes are written, which deserialization already handles
+        index.version = INDEX_VERSION;
+        Ok(index)
+    }
+}
+
+#[test]
+fn index_update_and_reload() {
+    let entry = |name: &str, hash: u64| ImageEntry {
+        hash,
+        path: PathBuf::from(name),
+        aliases: vec![],
+    };
+    let mut index = ImageIndex::build(
+        HashConfig::default(),
+        vec![
+            entry("a", 0),
+            entry("b", 0b11),
+            entry("c", u64::MAX),
+            entry("d", 0b1),
+        ],
+        10,
+        0,
+    );
+    assert_eq!(index.group_table.len(), 2);
+
+    // "b" is gone, "c" was edited and "e" is new
+    let (added, removed) = index.update(vec![
+        entry("a", 0),
+        entry("c", u64::MAX << 1),
+        entry("d", 0b1),
+        entry("e", u64::MAX),
+    ]);
+    assert_eq!((added, removed), (2, 2));
+    assert_eq!(index.image_info_list.len(), 4);
+    assert_eq!(index.circuit.len(), 4);
+    assert_eq!(index.mst.as_ref().unwrap().edges().count(), 3);
+
+    let dir = TempDir::new("index");
+    let path = dir.join("index.json");
+    index.save(&path).unwrap();
+    let loaded = ImageIndex::load(&path).unwrap();
+    assert_eq!(loaded.circuit, index.circuit);
+    assert_eq!(loaded.group_table.len(), 2);
+    assert_eq!(
+        loaded.mst.unwrap().total_weight(),
+        index.mst.unwrap().total_weight()
+    );
+}
@@ -0,0 +1,26 @@
+//! cooperative cancellation on ctrl-c, long running loops poll `requested` and wind down on their own
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// # Description
+/// the first ctrl-c only sets the flag so the run can stop cleanly and keep what it has,
+/// a second one exits right away
+pub fn install() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        eprintln!("interrupted, finishing up with what is done so far (ctrl-c again to quit now)");
+    })
+}
+
+/// whether ctrl-c was pressed
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+/// forgets an earlier ctrl-c, for commands where ctrl-c is the normal way to stop
+pub fn reset() {
+    REQUESTED.store(false, Ordering::SeqCst);
+}
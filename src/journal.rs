@@ -0,0 +1,232 @@
+//! append-only log of freshly computed hashes, so a long run that dies half way can be resumed
+use super::*;
+use crate::{cache, perceptual::HashConfig, records::HashRecord};
+use std::{
+    ffi::OsStr,
+    fs::{self, File, Metadata},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    sync::Mutex,
+};
+
+/// bumped whenever the layout of a journal record changes
+/// ## Comments
+/// - 1: records had no `version`, `size` or `mtime`
+/// - 2: records carry the size and mtime of the file they were computed from
+pub const JOURNAL_VERSION: u32 = 2;
+/// oldest version `Journal::resume` still understands, its records are read but never match
+pub const MIN_JOURNAL_VERSION: u32 = 1;
+
+/// a `HashRecord` along with the size and mtime of the file it was computed from
+#[derive(Serialize, Deserialize)]
+struct JournalRecord {
+    #[serde(default = "unversioned")]
+    version: u32,
+    #[serde(flatten)]
+    record: HashRecord,
+    // missing in journals written before they were recorded, those records never match
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    mtime: Option<(u64, u32)>,
+}
+
+/// # Description
+/// ndjson records appended and flushed one by one as images finish hashing
+/// ## Comments
+/// - the file is only created by the first `append`, commands that never hash leave an old journal alone
+/// - `finish` removes it once a run completed, a journal left on disk means the last run was cut short
+pub struct Journal {
+    location: PathBuf,
+    config: HashConfig,
+    writer: Mutex<Option<BufWriter<File>>>,
+    /// hashes recovered by `resume` with the size and mtime they were computed at, keyed by path as it was found
+    resumed: HashMap<PathBuf, (u64, (u64, u32), u64)>,
+}
+impl Journal {
+    /// # Description
+    /// `$XDG_CACHE_HOME/image_grouper/journal-<key>.ndjson`, falling back to `~/.cache`
+    /// ## Comments
+    /// the key is derived from `command`, so runs of different commands don't share a journal
+    /// while rerunning the same command finds the one it left behind
+    pub fn default_location<S: AsRef<OsStr>>(command: &[S]) -> Option<PathBuf> {
+        let cache_home = cache::cache_home()?;
+        let mut hasher = blake3::Hasher::new();
+        for part in command {
+            hasher.update(part.as_ref().as_encoded_bytes());
+            hasher.update(&[0]);
+        }
+        let key = hasher.finalize().to_hex();
+        Some(cache_home.join(format!("journal-{}.ndjson", &key[..16])))
+    }
+
+    /// a journal that starts over, anything already at `location` is replaced on the first append
+    pub fn new<P: AsRef<Path>>(location: P, config: &HashConfig) -> Self {
+        Self {
+            location: location.as_ref().to_path_buf(),
+            config: config.clone(),
+            writer: Mutex::new(None),
+            resumed: HashMap::new(),
+        }
+    }
+
+    /// # Description
+    /// picks up the journal left at `location` by an interrupted run
+    /// ## Comments
+    /// - reading stops at the first broken record, that's usually a line cut off by the crash
+    /// - the readable records are written straight back so new records don't land after a torn line
+    /// ## returns
+    /// an `InvalidData` error if the journal was made with a hash config incompatible with `config`
+    /// or by a version this one doesn't understand
+    pub fn resume<P: AsRef<Path>>(location: P, config: &HashConfig) -> io::Result<Self> {
+        let mut journal = Self::new(location, config);
+        let file = match File::open(&journal.location) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(journal),
+            Err(err) => return Err(err),
+        };
+        let mut recovered = vec![];
+        for line in BufReader::new(file).lines() {
+            let record = line.and_then(|line| {
+                serde_json::from_str::<JournalRecord>(&line).map_err(io::Error::from)
+            });
+            match record {
+                Ok(record) => {
+                    if !(MIN_JOURNAL_VERSION..=JOURNAL_VERSION).contains(&record.version) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "journal format version {} is not supported, expected {} to {}",
+                                record.version, MIN_JOURNAL_VERSION, JOURNAL_VERSION
+                            ),
+                        ));
+                    }
+                    config.check_compatible(&record.record.algorithm)?;
+                    recovered.push(record);
+                }
+                Err(err) => {
+                    eprintln!("journal {:?} ends early: {}", journal.location, err);
+                    break;
+                }
+            }
+        }
+        for record in &mut recovered {
+            record.version = JOURNAL_VERSION;
+            journal.write(record)?;
+        }
+        journal.resumed = recovered
+            .into_iter()
+            .filter_map(
+                |JournalRecord {
+                     record,
+                     size,
+                     mtime,
+                     ..
+                 }| { Some((record.path, (size?, mtime?, record.hash))) },
+            )
+            .collect();
+        Ok(journal)
+    }
+
+    pub fn location(&self) -> &Path {
+        &self.location
+    }
+
+    /// number of hashes recovered by `resume`
+    pub fn resumed_len(&self) -> usize {
+        self.resumed.len()
+    }
+
+    /// hash of `path` recovered from the interrupted run, as long as its size and mtime are unchanged
+    pub fn lookup(&self, path: &Path, metadata: &Metadata) -> Option<u64> {
+        let &(size, mtime, hash) = self.resumed.get(path)?;
+        (size == metadata.len() && Some(mtime) == cache::mtime(metadata)).then_some(hash)
+    }
+
+    /// # Description
+    /// records `entry` and flushes it to disk before returning, safe to call from several threads
+    /// ## Comments
+    /// `metadata` is the file's the hash was computed from, for archive members that's the archive
+    pub fn append(&self, entry: &ImageEntry, metadata: &Metadata) -> io::Result<()> {
+        self.write(&JournalRecord {
+            version: JOURNAL_VERSION,
+            record: HashRecord::new(entry, &self.config),
+            size: Some(metadata.len()),
+            mtime: cache::mtime(metadata),
+        })
+    }
+
+    fn write(&self, record: &JournalRecord) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap();
+        if writer.is_none() {
+            if let Some(parent) = self.location.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            *writer = Some(BufWriter::new(File::create(&self.location)?));
+        }
+        let writer = writer.as_mut().unwrap();
+        serde_json::to_writer(&mut *writer, record)?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+
+    /// removes the journal after a completed run, does nothing if this run never wrote one
+    pub fn finish(self) -> io::Result<()> {
+        if self.writer.into_inner().unwrap().is_some() {
+            fs::remove_file(&self.location)?;
+        }
+        Ok(())
+    }
+}
+
+fn unversioned() -> u32 {
+    1
+}
+
+#[test]
+fn journal_resumes_after_torn_line() {
+    let dir = TempDir::new("journal");
+    let location = dir.join("journal.ndjson");
+    let config = HashConfig::new(HashType::AHASH);
+    let entry = |hash: u64| ImageEntry {
+        hash,
+        path: PathBuf::from(format!("{}.png", hash)),
+        aliases: vec![],
+    };
+    let source = dir.join("source.png");
+    fs::write(&source, b"image").unwrap();
+    let metadata = fs::metadata(&source).unwrap();
+
+    let journal = Journal::new(&location, &config);
+    journal.append(&entry(1), &metadata).unwrap();
+    journal.append(&entry(2), &metadata).unwrap();
+    drop(journal);
+    // a crash in the middle of a write
+    let mut file = fs::OpenOptions::new().append(true).open(&location).unwrap();
+    file.write_all(b"{\"path\":\"3.p").unwrap();
+    drop(file);
+
+    let journal = Journal::resume(&location, &config).unwrap();
+    assert_eq!(journal.resumed_len(), 2);
+    assert_eq!(journal.lookup(Path::new("2.png"), &metadata), Some(2));
+    journal.append(&entry(3), &metadata).unwrap();
+    drop(journal);
+
+    let journal = Journal::resume(&location, &config).unwrap();
+    assert_eq!(journal.lookup(Path::new("3.png"), &metadata), Some(3));
+    // the file was edited after it was journaled
+    fs::write(&source, b"edited image").unwrap();
+    let edited = fs::metadata(&source).unwrap();
+    assert_eq!(journal.lookup(Path::new("3.png"), &edited), None);
+    assert!(Journal::resume(&location, &HashConfig::new(HashType::DHASH)).is_err());
+    journal.finish().unwrap();
+    assert!(!location.exists());
+
+    // records from before the version was written are read but can't be trusted without size and mtime
+    let mut legacy = serde_json::to_value(HashRecord::new(&entry(4), &config)).unwrap();
+    fs::write(&location, format!("{}\n", legacy)).unwrap();
+    let journal = Journal::resume(&location, &config).unwrap();
+    assert_eq!(journal.lookup(Path::new("4.png"), &edited), None);
+    legacy["version"] = (JOURNAL_VERSION + 1).into();
+    fs::write(&location, format!("{}\n", legacy)).unwrap();
+    assert!(Journal::resume(&location, &config).is_err());
+}
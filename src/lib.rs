@@ -1,43 +1,119 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
+pub mod archive;
+pub mod bktree;
+pub mod cache;
+pub mod duplicates;
+pub mod export;
+pub mod extattr;
 pub mod filesysutils;
 pub mod graph;
+pub mod hexhash;
+pub mod imageformat;
+pub mod index;
+pub mod interrupt;
+pub mod journal;
+pub mod merge;
 pub mod perceptual;
+pub mod query;
+pub mod records;
+pub mod source;
+pub mod sqlite;
+#[cfg(test)]
+mod testdir;
+pub mod watch;
 
+#[cfg(test)]
+use testdir::TempDir;
 
 pub const VALID_IMAGE_EXTS: &[&str] = &["bmp", "png", "jpg", "jpeg", "gif", "tga", "tiff", "ppm"];
 pub type GroupID = usize;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum HashType {
+    #[default]
     AHASH = 0,
     DHASH = 1,
     PHASH = 2,
 }
-impl Default for HashType {
-    fn default() -> Self {
-        Self::AHASH
+impl HashType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ahash" => Some(Self::AHASH),
+            "dhash" => Some(Self::DHASH),
+            "phash" => Some(Self::PHASH),
+            _ => None,
+        }
+    }
+
+    /// the name used on the command line and in serialized data
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::AHASH => "ahash",
+            Self::DHASH => "dhash",
+            Self::PHASH => "phash",
+        }
     }
 }
 
-#[derive(Clone, Serialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ImageInfo {
+    #[serde(with = "hexhash")]
     pub hash: u64,
     pub image_idx: usize,
 }
 
-#[derive(Clone, Serialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct GroupInfo {
+    #[serde(with = "hexhash")]
     pub hash: u64,
     pub similar_images: Vec<ImageInfo>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ImageEntry {
+    #[serde(with = "hexhash")]
     pub hash: u64,
     pub path: PathBuf,
+    /// other paths with the same contents: links to the same file (hardlinks, bind mounts) as well as
+    /// byte-identical copies, `path` is the first of them in sorted order. the `--duplicates` report tells
+    /// the copies apart
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<PathBuf>,
+}
+
+/// # Description
+/// collects the members of every group
+/// ## Comments
+/// `groups` maps an index of `nodes` to its group (see `HammingMST::groups`), nodes missing from it are left out
+pub fn build_group_table(
+    nodes: &[ImageEntry],
+    groups: &HashMap<usize, GroupID>,
+) -> HashMap<GroupID, GroupInfo> {
+    let mut members = groups
+        .iter()
+        .map(|(&idx, &group)| (group, idx))
+        .collect::<Vec<_>>();
+    members.sort_unstable();
+
+    let mut group_table = HashMap::<GroupID, GroupInfo>::new();
+    for (group, image_idx) in members {
+        group_table
+            .entry(group)
+            .or_insert_with(|| GroupInfo {
+                hash: nodes[group].hash,
+                similar_images: vec![],
+            })
+            .similar_images
+            .push(ImageInfo {
+                hash: nodes[image_idx].hash,
+                image_idx,
+            });
+    }
+    group_table
 }
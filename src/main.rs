@@ -1,13 +1,36 @@
-use clap::{arg, command, Command};
-use image_grouper::{filesysutils::*, graph::HammingMST, perceptual, *};
+use clap::{arg, command, ArgMatches, Command};
+use image_grouper::{
+    archive::{self, ArchiveKind},
+    cache::HashCache,
+    duplicates::{self, DuplicateSet},
+    export::{ExportFormat, GraphExport},
+    filesysutils::*,
+    graph::KnnGraph,
+    index::ImageIndex,
+    journal::Journal,
+    merge::{Merger, PathRemap},
+    perceptual::{HashConfig, ResizeFilter},
+    query::ImageQuery,
+    records::{self, HashRecord, RecordFormat, RecordWriter},
+    source::{self, ImageSource, LocalDirectory, PathList, UrlList},
+    watch::{self, DirectoryWatch},
+    *,
+};
 use path_absolutize::*;
 use rayon::prelude::*;
 use serde::Serialize;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     env,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
-    time::{Duration, Instant},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
 };
 
 #[derive(Serialize)]
@@ -27,124 +50,1607 @@ fn main() {
         .arg(arg!( -o --output [OUTPUT_DIRECTORY] "directory of sorted files").max_values(1))
         .arg(
             arg!(
-                -i --images <IMAGE_FILES> "expects specific paths of images to group"
+                -i --images <IMAGE_FILES> "expects specific paths of images to group, '-' reads a list from stdin"
             )
             // We don't have syntax yet for optional options, so manually calling `required`
             .required(false)
             // Support non-UTF8 paths
             .allow_invalid_utf8(true)
+            .global(true)
             .min_values(1),
         )
+        .arg(
+            arg!(--"files-from" <FILE> "newline or NUL delimited list of images or directories to use, '-' is stdin")
+                .required(false)
+                .allow_invalid_utf8(true)
+                .global(true),
+        )
+        .arg(
+            arg!(--urls <FILE> "newline separated list of http(s) image urls to fetch and use, '-' is stdin")
+                .required(false)
+                .global(true),
+        )
         .arg(
             arg!(
-                -f --func <TYPE> ... "hash function. TYPE can be: 'phash' 'ahash' or 'dhash'"
+                -f --func <TYPE> ... "hash function. TYPE can be: 'ahash' or 'dhash', 'phash' isn't implemented yet"
             )
             .required(false)
+            .global(true)
             .default_value("ahash"),
         )
+        .arg(
+            arg!(--filter <FILTER> "resize filter. FILTER can be: 'nearest' 'triangle' 'catmullrom' 'gaussian' or 'lanczos3'")
+                .required(false)
+                .global(true)
+                .default_value("gaussian"),
+        )
+        .arg(
+            arg!(--cache <FILE> "hash cache location, defaults to $XDG_CACHE_HOME/image_grouper/hashes.json")
+                .required(false)
+                .global(true),
+        )
+        .arg(arg!(--"no-cache" "decode and hash every image even if it is cached").global(true))
+        .arg(
+            arg!(--journal <FILE> "new hashes are appended here as they finish, defaults to one per command under $XDG_CACHE_HOME/image_grouper")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            arg!(--"follow-symlinks" "follow symlinked files and directories while scanning (default)")
+                .global(true)
+                .overrides_with("no-follow-symlinks"),
+        )
+        .arg(
+            arg!(--"no-follow-symlinks" "skip symlinks found while scanning, the given directories are still followed")
+                .global(true)
+                .overrides_with("follow-symlinks"),
+        )
+        .arg(
+            arg!(--include <GLOB> ... "only scan files matching GLOB, matched against the relative path and the file name")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            arg!(--exclude <GLOB> ... "skip files and directories matching GLOB, e.g. '@eaDir' or 'exports/**'")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            arg!(--"max-depth" <DEPTH> "scan at most DEPTH levels below the given directories, 1 is only their own files")
+                .required(false)
+                .global(true),
+        )
+        .arg(
+            arg!(--archives "also hash the images inside zip, cbz, tar and tar.gz archives, named archive.zip!/inner/path.png")
+                .global(true),
+        )
+        .arg(
+            arg!(--"require-extension" "only look at files with an image extension (any case) instead of sniffing every file's contents")
+                .global(true),
+        )
+        .arg(arg!(--"skip-hidden" "skip files and directories whose name starts with a dot").global(true))
+        .arg(arg!(--"no-ignore" "don't honour .gitignore and .ignore files while scanning").global(true))
+        .arg(
+            arg!(--xattr "read and store hashes in the user.image_grouper.hash extended attribute of each image")
+                .global(true),
+        )
+        .arg(
+            arg!(--"no-exact-duplicates" "hash byte-identical files one by one instead of merging them into one image")
+                .global(true),
+        )
+        .arg(
+            arg!(--duplicates <FILE> "write the byte-identical files that were merged to FILE as json")
+                .required(false)
+                .allow_invalid_utf8(true)
+                .global(true),
+        )
+        .arg(arg!(--resume "reuse the hashes journaled by an interrupted run").global(true))
+        .arg(
+            arg!(--threshold <DISTANCE> "images closer than DISTANCE (0-100) share a group")
+                .required(false)
+                .global(true)
+                .default_value("10"),
+        )
+        .arg(
+            arg!(--index <FILE> "saved index to start from, it is created or updated when directories are given too")
+                .required(false)
+                .global(true),
+        )
         .subcommand(
             Command::new("test")
                 .about("does testing things")
                 .arg(arg!(-l --list "lists test values")),
         )
+        .subcommand(
+            Command::new("export")
+                .about("writes the similarity graph as Graphviz DOT, GraphML or node-link JSON")
+                .arg(
+                    arg!([directory] "will recursively traverse from here to collect images")
+                        .min_values(1),
+                )
+                .arg(arg!(-o --output <FILE> "file the graph is written to"))
+                .arg(
+                    arg!(--format <FORMAT> "FORMAT can be: 'dot' 'graphml' 'json' or 'sqlite'")
+                        .required(false)
+                        .default_value("dot"),
+                )
+                .arg(
+                    arg!(--knn <K> "export the k-nearest-neighbour graph instead of the spanning tree")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("query")
+                .about("finds the images most similar to the given query images")
+                .arg(arg!(<image> "images to look up").min_values(1))
+                .arg(
+                    arg!(-d --directory <DIRECTORY> "will recursively traverse from here to collect images")
+                        .required(false)
+                        .min_values(1),
+                )
+                .arg(
+                    arg!(-k --count <K> "number of matches reported per query image")
+                        .required(false)
+                        .default_value("5"),
+                ),
+        )
+        .subcommand(
+            Command::new("hash")
+                .about("writes path,algorithm,hash records as csv or ndjson instead of grouping")
+                .arg(
+                    arg!([directory] "will recursively traverse from here to collect images")
+                        .min_values(1),
+                )
+                .arg(arg!(-o --output <FILE> "file the records are written to"))
+                .arg(
+                    arg!(--format <FORMAT> "FORMAT can be: 'csv' or 'ndjson', guessed from the file extension by default")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("import")
+                .about("groups hashes read from csv or ndjson records without opening the images")
+                .arg(arg!(<records> "record files, '-' reads stdin").min_values(1))
+                .arg(arg!(-o --output [OUTPUT_DIRECTORY] "directory of sorted files").max_values(1))
+                .arg(
+                    arg!(--format <FORMAT> "FORMAT can be: 'csv' or 'ndjson', guessed from the file extension by default")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("merge")
+                .about("groups the union of saved indexes, hash caches and record files from several machines")
+                .arg(arg!(<inputs> "indexes, caches or csv/ndjson record files, earlier inputs win duplicate paths").min_values(1))
+                .arg(arg!(-o --output [OUTPUT_DIRECTORY] "directory of sorted files").max_values(1))
+                .arg(
+                    arg!(--remap <MAPPING> ... "FROM=TO, paths starting with FROM are rewritten to start with TO")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("watch")
+                .about("groups the directories, then keeps the output and --index up to date as images come and go")
+                .arg(
+                    arg!(<directory> "directories to group and watch, recursively")
+                        .min_values(1),
+                )
+                .arg(arg!(-o --output [OUTPUT_DIRECTORY] "directory of sorted files").max_values(1))
+                .arg(
+                    arg!(--debounce <MILLISECONDS> "wait until the directories were quiet this long before updating")
+                        .required(false)
+                        .default_value("2000"),
+                ),
+        )
         .get_matches();
 
-    let hash_method = match matches.value_of("func").unwrap_or("ahash") {
-        "ahash" => HashType::AHASH,
-        "dhash" => HashType::DHASH,
-        "phash" => HashType::PHASH,
-        _ => HashType::default(),
+    let hash_method = match HashType::from_name(matches.value_of("func").unwrap_or("ahash")) {
+        Some(hash_method) => hash_method,
+        None => {
+            eprintln!(
+                "unknown hash function '{}'",
+                matches.value_of("func").unwrap_or_default()
+            );
+            return;
+        }
     };
+    // caught here, hashing nothing would otherwise replace an existing index with an empty one
+    if let HashType::PHASH = hash_method {
+        eprintln!("[{:?}] not implemented", hash_method);
+        return;
+    }
+    let mut hash_config = HashConfig::new(hash_method);
+    hash_config.filter =
+        match ResizeFilter::from_name(matches.value_of("filter").unwrap_or("gaussian")) {
+            Some(filter) => filter,
+            None => {
+                eprintln!(
+                    "unknown resize filter '{}'",
+                    matches.value_of("filter").unwrap_or_default()
+                );
+                return;
+            }
+        };
 
-    let output_directory: &Path = matches.value_of("output").unwrap_or("./sorted").as_ref();
-
-    if let Some(directories) = matches.values_of("directory") {
-        //setup an iterator to bfs the filesystem for image files
-        let file_iterator = directories
-            .map(|dir| {
-                let dir: &Path = dir.as_ref();
-                dir
+    let mut cache = if matches.is_present("no-cache") {
+        None
+    } else {
+        matches
+            .value_of("cache")
+            .map(PathBuf::from)
+            .or_else(HashCache::default_location)
+            .map(|location| match HashCache::open(&location) {
+                Ok(cache) => cache,
+                Err(err) => {
+                    eprintln!("starting a new hash cache at {:?}: {}", location, err);
+                    HashCache::new(&location)
+                }
             })
-            .filter(|dir| dir.is_dir())
-            .flat_map(|path| {
-                FileSystemIterator::new(path)
-                    .filter(|path| path.is_file())
-                    .filter(|path| path.extension().is_some())
-                    .filter(|image_file| {
-                        let ext = image_file.extension().unwrap().to_str().unwrap_or_default();
-                        VALID_IMAGE_EXTS.contains(&ext)
-                    })
-                    .filter_map(|file| image::open(&file).ok().zip(Some(file)))
-            });
-
-        //execute iterator here
-        let image_info_list = match hash_method {
-            HashType::AHASH => {
-                // println!("picked ahash");
-                file_iterator
-                    .par_bridge()
-                    .map(|(img, path)| ImageEntry {
-                        hash: {
-                            let h = perceptual::ahash(&img);
-                            println!("{:?} hashed...", path);
-                            h
-                        },
-                        path,
-                    })
-                    .collect::<Vec<_>>()
-            }
-            HashType::DHASH => {
-                // println!("picked dhash");
-                file_iterator
-                    .par_bridge()
-                    .map(|(img, path)| ImageEntry {
-                        hash: {
-                            let h = perceptual::dhash(&img);
-                            println!("{:?} hashed...", path);
-                            h
+    };
+
+    if let Err(err) = interrupt::install() {
+        eprintln!("ctrl-c will stop without saving anything: {}", err);
+    }
+
+    let journal = matches
+        .value_of("journal")
+        .map(PathBuf::from)
+        .or_else(|| {
+            // rerunning the same command from the same directory finds the same journal
+            let mut command = vec![env::current_dir().ok()?.into_os_string()];
+            command.extend(env::args_os().skip(1).filter(|arg| arg != "--resume"));
+            Journal::default_location(&command)
+        })
+        .map(|location| {
+            if !matches.is_present("resume") {
+                return Journal::new(&location, &hash_config);
+            }
+            match Journal::resume(&location, &hash_config) {
+                Ok(journal) => journal,
+                Err(err) => {
+                    eprintln!("not resuming from journal {:?}: {}", location, err);
+                    Journal::new(&location, &hash_config)
+                }
+            }
+        });
+
+    match matches.subcommand() {
+        Some(("export", sub_matches)) => {
+            export_graph(sub_matches, &hash_config, &mut cache, journal.as_ref())
+        }
+        Some(("query", sub_matches)) => {
+            query_images(sub_matches, &hash_config, &mut cache, journal.as_ref())
+        }
+        Some(("hash", sub_matches)) => {
+            hash_to_records(sub_matches, &hash_config, &mut cache, journal.as_ref())
+        }
+        Some(("import", sub_matches)) => import_records(sub_matches),
+        Some(("merge", sub_matches)) => merge_inputs(sub_matches, &hash_config),
+        Some(("watch", sub_matches)) => {
+            watch_images(sub_matches, &hash_config, &mut cache, journal.as_ref())
+        }
+        _ => group_images(&matches, &hash_config, &mut cache, journal.as_ref()),
+    }
+
+    if interrupt::requested() {
+        if let Some(journal) = journal {
+            eprintln!(
+                "stopped early, rerun with --resume to reuse the hashes journaled in {:?}",
+                journal.location()
+            );
+        }
+        std::process::exit(130);
+    }
+
+    // the run went through, the next one has nothing to resume
+    if let Some(journal) = journal {
+        let location = journal.location().to_path_buf();
+        if let Err(err) = journal.finish() {
+            eprintln!("failed to remove journal {:?}: {}", location, err);
+        }
+    }
+}
+
+/// # Description
+/// hashes `files` in parallel, entries come back in the same order
+/// ## Comments
+/// images found in `cache` with an unchanged size and mtime aren't decoded at all, neither are images
+/// recovered by a resumed `journal` or, with `use_xattrs`, stored in the file's extended attribute.
+/// every newly computed hash is appended to `journal` right away, with `use_xattrs` every hash that
+/// wasn't read from the attribute is written to it.
+/// files are recognized by their contents unless `require_extension` is set, the ones that look like
+/// images but fail to decode are listed at the end.
+/// unless `exact_duplicates` is off, byte-identical files are merged into one entry before anything is decoded.
+/// `on_hashed` sees every entry as soon as it is ready, from whichever thread produced it
+fn hash_files(
+    files: Vec<InputFile>,
+    hash_config: &HashConfig,
+    cache: &mut Option<HashCache>,
+    journal: Option<&Journal>,
+    options: &HashOptions,
+    on_hashed: &(dyn Fn(&ImageEntry) + Sync),
+) -> Vec<ImageEntry> {
+    if let Some(journal) = journal.filter(|j| j.resumed_len() > 0) {
+        println!("resuming with {} journaled hashes", journal.resumed_len());
+    }
+
+    let (files, duplicate_sets) = if options.exact_duplicates {
+        duplicates::collapse(files, cache.as_mut(), &|path| options.may_hash(path))
+    } else {
+        (files, vec![])
+    };
+
+    // plain files are decoded through it, archive members are read straight from their archive
+    let source = PathList(files.iter().map(|file| file.path.clone()).collect());
+    //execute iterator here
+    let cached = cache.as_ref();
+    // only the first failure is reported, it's usually the filesystem lacking support
+    let xattr_failed = AtomicBool::new(false);
+    let undecodable = Mutex::new(vec![]);
+    let report_undecodable =
+        |path: PathBuf, err: String| undecodable.lock().unwrap().push((path, err));
+    // journals fresh hashes and hands every entry to `on_hashed`
+    let finish =
+        |path: PathBuf, aliases: Vec<PathBuf>, hash: u64, fresh: bool, metadata: &fs::Metadata| {
+            let entry = ImageEntry {
+                hash,
+                path,
+                aliases,
+            };
+            if fresh {
+                println!("{:?} hashed...", entry.path);
+                if let Some(journal) = journal {
+                    if let Err(err) = journal.append(&entry, metadata) {
+                        eprintln!("failed to journal {:?}: {}", entry.path, err);
+                    }
+                }
+            }
+            on_hashed(&entry);
+            entry
+        };
+    let known_hash = |path: &Path, metadata: &fs::Metadata| {
+        let cached_hash = cached.and_then(|c| c.lookup(path, metadata, hash_config));
+        (
+            cached_hash,
+            cached_hash.or_else(|| journal.and_then(|j| j.lookup(path, metadata))),
+        )
+    };
+    // members share the archive's size and mtime, so the cache drops them all once the archive changes
+    let hash_archive = |archive_path: &Path, aliases: &[PathBuf], metadata: &fs::Metadata| {
+        let mut results = vec![];
+        let read = archive::for_each_member(archive_path, |inner, reader| {
+            if interrupt::requested()
+                || (options.require_extension
+                    && !imageformat::has_image_extension(Path::new(inner)))
+            {
+                return;
+            }
+            let path = archive::member_path(archive_path, inner);
+            let (cached_hash, known) = known_hash(&path, metadata);
+            let hash = match known {
+                Some(hash) => hash,
+                None => {
+                    let mut bytes = vec![];
+                    if let Err(err) = reader.read_to_end(&mut bytes) {
+                        report_undecodable(path, err.to_string());
+                        return;
+                    }
+                    let looks_like_image = imageformat::has_image_extension(Path::new(inner))
+                        || imageformat::sniff_bytes(&bytes).is_some();
+                    if !looks_like_image {
+                        return;
+                    }
+                    match imageformat::decode(Path::new(inner), bytes) {
+                        Ok(img) => match hash_config.hash(&img) {
+                            Some(hash) => hash,
+                            None => return,
                         },
-                        path,
-                    })
-                    .collect::<Vec<_>>()
+                        Err(err) => {
+                            report_undecodable(path, err.to_string());
+                            return;
+                        }
+                    }
+                }
+            };
+            let fresh = known.is_none();
+            let member_aliases = aliases
+                .iter()
+                .map(|alias| archive::member_path(alias, inner))
+                .collect();
+            let entry = finish(path, member_aliases, hash, fresh, metadata);
+            results.push((entry, cached_hash.is_none().then(|| metadata.clone())));
+        });
+        if let Err(err) = read {
+            report_undecodable(archive_path.to_path_buf(), err.to_string());
+        }
+        results.sort_by(|(a, _), (b, _)| a.path.cmp(&b.path));
+        results
+    };
+    let hash_file = |InputFile { path, aliases }: InputFile, metadata: fs::Metadata| {
+        if !options.require_extension && !imageformat::looks_like_image(&path) {
+            return None;
+        }
+        if options.use_xattrs {
+            if let Some(hash) = extattr::read(&path, &metadata, hash_config) {
+                return Some((
+                    finish(path, aliases, hash, false, &metadata),
+                    Some(metadata),
+                ));
             }
-            _ => {
-                eprintln!("[{:?}] not implemented", hash_method);
-                vec![]
+        }
+        let (cached_hash, known) = known_hash(&path, &metadata);
+        let hash = match known {
+            Some(hash) => hash,
+            None => {
+                let img = match source.decode(&path) {
+                    Ok(img) => img,
+                    Err(err) => {
+                        report_undecodable(path, err.to_string());
+                        return None;
+                    }
+                };
+                hash_config.hash(&img)?
             }
         };
+        if options.use_xattrs {
+            if let Err(err) = extattr::write(&path, &metadata, hash_config, hash) {
+                if !xattr_failed.swap(true, Ordering::Relaxed) {
+                    eprintln!("failed to store hash attribute on {:?}: {}", path, err);
+                }
+            }
+        }
+        let entry = finish(path, aliases, hash, known.is_none(), &metadata);
+        Some((entry, cached_hash.is_none().then_some(metadata)))
+    };
+    let results = files
+        .into_par_iter()
+        .flat_map_iter(|file| {
+            if interrupt::requested() {
+                return vec![];
+            }
+            let metadata = match fs::metadata(&file.path) {
+                Ok(metadata) if metadata.is_file() => metadata,
+                _ => return vec![],
+            };
+            if options.archives && ArchiveKind::from_path(&file.path).is_some() {
+                return hash_archive(&file.path, &file.aliases, &metadata);
+            }
+            hash_file(file, metadata).into_iter().collect()
+        })
+        .collect::<Vec<_>>();
+    if interrupt::requested() {
+        println!("hashing interrupted after {} images", results.len());
+    }
+    let mut undecodable = undecodable.into_inner().unwrap();
+    if !undecodable.is_empty() {
+        undecodable.sort();
+        eprintln!(
+            "{} files look like images but couldn't be decoded:",
+            undecodable.len()
+        );
+        for (path, err) in &undecodable {
+            eprintln!("  {:?}: {}", path, err);
+        }
+    }
+
+    if let Some(cache) = cache {
+        let mut cache_hits = 0;
+        for (entry, metadata) in &results {
+            match metadata {
+                Some(metadata) => cache.insert(&entry.path, metadata, hash_config, entry.hash),
+                None => cache_hits += 1,
+            }
+        }
+        println!(
+            "{} of {} hashes loaded from cache",
+            cache_hits,
+            results.len()
+        );
+        // saved even when interrupted, everything in it is a finished hash
+        if let Err(err) = cache.save() {
+            eprintln!("failed to save hash cache {:?}: {}", cache.location(), err);
+        }
+    }
+
+    let image_info_list = results
+        .into_iter()
+        .map(|(entry, _)| entry)
+        .collect::<Vec<_>>();
+    report_duplicates(duplicate_sets, &image_info_list, options);
+    image_info_list
+}
+
+/// # Description
+/// tells how many exact duplicates were merged and writes the sets to `--duplicates`
+/// ## Comments
+/// sets of files that turned out not to be images are dropped, archives count when any member was hashed
+fn report_duplicates(
+    mut sets: Vec<DuplicateSet>,
+    image_info_list: &[ImageEntry],
+    options: &HashOptions,
+) {
+    let hashed = image_info_list
+        .iter()
+        .map(|entry| {
+            archive::split_member_path(&entry.path)
+                .filter(|_| !entry.path.exists())
+                .map_or(entry.path.as_path(), |(archive, _)| archive)
+        })
+        .collect::<HashSet<_>>();
+    sets.retain(|set| hashed.contains(set.paths[0].as_path()));
+    let copies = sets.iter().map(|set| set.paths.len() - 1).sum::<usize>();
+    if copies > 0 {
+        println!(
+            "{} byte-identical copies of {} images merged",
+            copies,
+            sets.len()
+        );
+    }
+    if let Some(report_path) = &options.duplicate_report {
+        let written = File::create(report_path).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            serde_json::to_writer_pretty(&mut writer, &sets)?;
+            writer.flush()
+        });
+        match written {
+            Ok(()) => println!("exact duplicates written to {:?}", report_path),
+            Err(err) => eprintln!("failed to write {:?}: {}", report_path, err),
+        }
+    }
+}
+
+/// # Description
+/// every file a command should hash: the images under its directories, the `-i` images and the
+/// paths listed by `--files-from`
+/// ## Comments
+/// `-` as a directory or `-i` image reads a newline or NUL delimited list from stdin, just like `--files-from -`.
+/// listed directories are walked as well, listed files are taken as they are
+/// ## returns
+/// `None` if the command wasn't given any inputs, otherwise the files sorted and without duplicates,
+/// paths of the same file (hardlinks, bind mounts) are merged into one input with aliases
+fn input_files(matches: &ArgMatches, options: &HashOptions) -> Option<Vec<InputFile>> {
+    let stdin = Path::new("-");
+    let mut inputs = matches
+        .values_of("directory")
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .chain(
+            matches
+                .values_of_os("images")
+                .into_iter()
+                .flatten()
+                .map(PathBuf::from),
+        )
+        .collect::<Vec<_>>();
+    let list_files = matches
+        .value_of_os("files-from")
+        .map(Path::new)
+        .into_iter()
+        .chain(inputs.iter().any(|input| input == stdin).then_some(stdin))
+        .collect::<HashSet<_>>();
+    if inputs.is_empty() && list_files.is_empty() {
+        return None;
+    }
+
+    let mut listed = vec![];
+    for list_file in list_files {
+        let list = if list_file == stdin {
+            filesysutils::read_path_list(io::stdin().lock())
+        } else {
+            File::open(list_file).and_then(filesysutils::read_path_list)
+        };
+        match list {
+            Ok(list) => listed.extend(list),
+            Err(err) => eprintln!("failed to read file list {:?}: {}", list_file, err),
+        }
+    }
+    inputs.retain(|input| input != stdin);
+    inputs.extend(listed);
 
-        println!("creating minimum spanning tree...");
-        let mimimum_spanning_tree = HammingMST::new(&image_info_list).unwrap();
+    let (directories, listed): (Vec<_>, Vec<_>) =
+        inputs.into_iter().partition(|input| input.is_dir());
+    let mut files = vec![];
+    for root in directories {
+        if interrupt::requested() {
+            break;
+        }
+        let directory = LocalDirectory {
+            root,
+            options: options.scan.clone(),
+        };
+        match directory.files() {
+            Ok(found) => files.extend(found.into_iter().map(|(path, id)| (path, Some(id)))),
+            Err(err) => eprintln!("failed to scan {:?}: {}", directory.root, err),
+        }
+    }
+    // listed files are looked up by `merge_aliases`
+    if let Ok(listed) = PathList(listed).items() {
+        files.extend(listed.into_iter().map(|path| (path, None)));
+    }
+    let mut files = files
+        .into_iter()
+        .filter(|(path, _)| {
+            !options.require_extension
+                || imageformat::has_image_extension(path)
+                || (options.archives && ArchiveKind::from_path(path).is_some())
+        })
+        .collect::<Vec<_>>();
+    files.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    files.dedup_by(|(a, _), (b, _)| a == b);
+    Some(merge_aliases(files))
+}
 
-        // println!("{:?}", image_info_list);
-        // println!("{:?}", mimimum_spanning_tree);
+/// # Description
+/// hashes everything a command was given, the local files through `hash_files` and the `--urls` after them
+/// ## returns
+/// `None` if the command wasn't given any inputs
+fn hash_inputs(
+    matches: &ArgMatches,
+    hash_config: &HashConfig,
+    cache: &mut Option<HashCache>,
+    journal: Option<&Journal>,
+    options: &HashOptions,
+    on_hashed: &(dyn Fn(&ImageEntry) + Sync),
+) -> Option<Vec<ImageEntry>> {
+    let files = input_files(matches, options);
+    let urls = url_list(matches);
+    if files.is_none() && urls.is_none() {
+        return None;
+    }
+    let mut image_info_list = match files {
+        Some(files) => hash_files(files, hash_config, cache, journal, options, on_hashed),
+        None => vec![],
+    };
+    if let Some(urls) = urls {
+        let on_downloaded = |entry: &ImageEntry| {
+            println!("{:?} hashed...", entry.path);
+            on_hashed(entry);
+        };
+        match source::hash_source(&urls, hash_config, &on_downloaded) {
+            Ok(entries) => image_info_list.extend(entries),
+            Err(err) => eprintln!("failed to list urls: {}", err),
+        }
+    }
+    Some(image_info_list)
+}
 
-        let mut file_name = 0;
-        let mut sym_link_path = PathBuf::new();
+/// the `--urls` list, `None` if there is none or it can't be read
+fn url_list(matches: &ArgMatches) -> Option<UrlList> {
+    let list_file = matches.value_of("urls")?;
+    let urls = if list_file == "-" {
+        UrlList::from_reader(io::stdin().lock())
+    } else {
+        File::open(list_file).and_then(|file| UrlList::from_reader(BufReader::new(file)))
+    };
+    urls.map_err(|err| eprintln!("failed to read url list {:?}: {}", list_file, err))
+        .ok()
+}
 
-        std::fs::create_dir(output_directory);
+/// command line switches deciding which files `hash_files` hashes and how
+#[derive(Clone)]
+struct HashOptions {
+    scan: ScanOptions,
+    use_xattrs: bool,
+    /// only consider files with an image extension instead of sniffing every file
+    require_extension: bool,
+    /// hash the images inside zip and tar archives
+    archives: bool,
+    /// merge byte-identical files before hashing
+    exact_duplicates: bool,
+    /// where the merged byte-identical files are listed
+    duplicate_report: Option<PathBuf>,
+}
 
-        let mut circuit = mimimum_spanning_tree
-            .iter()
-            .filter_map(|a| a)
-            .collect::<Vec<_>>();
+impl HashOptions {
+    /// whether `hash_files` could get as far as decoding `path`, nothing else is worth reading in full
+    fn may_hash(&self, path: &Path) -> bool {
+        (self.archives && ArchiveKind::from_path(path).is_some())
+            || self.require_extension
+            || imageformat::looks_like_image(path)
+    }
+}
+
+fn hash_options(matches: &ArgMatches) -> HashOptions {
+    HashOptions {
+        scan: scan_options(matches),
+        use_xattrs: matches.is_present("xattr"),
+        require_extension: matches.is_present("require-extension"),
+        archives: matches.is_present("archives"),
+        exact_duplicates: !matches.is_present("no-exact-duplicates"),
+        duplicate_report: matches.value_of_os("duplicates").map(PathBuf::from),
+    }
+}
+
+/// # Description
+/// how the directories given to a command are walked
+/// ## Comments
+/// the output in effect (`-o`, or `./sorted` for commands that default to it) is never scanned, it only holds links to images found elsewhere.
+/// invalid globs end the program with a usage error
+fn scan_options(matches: &ArgMatches) -> ScanOptions {
+    // commands without `-o` write nothing that could be scanned back in
+    let excluded = if matches.is_valid_arg("output") {
+        vec![PathBuf::from(
+            matches.value_of("output").unwrap_or("./sorted"),
+        )]
+    } else {
+        vec![]
+    };
+    let globs = |name: &str| {
+        filesysutils::glob_set(matches.values_of(name).into_iter().flatten()).unwrap_or_else(
+            |err| clap::Error::raw(clap::ErrorKind::ValueValidation, format!("{}\n", err)).exit(),
+        )
+    };
+    let max_depth = matches.is_present("max-depth").then(|| {
+        matches
+            .value_of_t::<usize>("max-depth")
+            .unwrap_or_else(|err| err.exit())
+    });
+    ScanOptions {
+        follow_symlinks: !matches.is_present("no-follow-symlinks"),
+        excluded,
+        include: globs("include"),
+        exclude: globs("exclude"),
+        max_depth,
+        hidden: !matches.is_present("skip-hidden"),
+        ignore_files: !matches.is_present("no-ignore"),
+    }
+}
 
-        // spend extactly 10 seconds iteratively improving the tour
-        graph::iteratively_improve_tour(30_000_000, 10_000, &mut circuit, &image_info_list);
+/// # Description
+/// produces the index a command works on
+/// ## Comments
+/// - only `--index`: the saved index is loaded as is, along with the hash config it was made with
+/// - directories and an existing `--index`: the index is updated incrementally and saved again
+/// - directories and a new `--index`: everything is built from scratch and saved
+///
+/// the tour is only optimized when `optimize_tour` is set or the index is going to be saved
+fn obtain_index(
+    matches: &ArgMatches,
+    hash_config: &HashConfig,
+    cache: &mut Option<HashCache>,
+    journal: Option<&Journal>,
+    optimize_tour: bool,
+) -> Option<ImageIndex> {
+    let index_path = matches.value_of("index");
+    let threshold = matches
+        .value_of_t::<u64>("threshold")
+        .unwrap_or_else(|err| err.exit());
+    let threshold_given = matches.occurrences_of("threshold") > 0;
 
-        circuit.iter().for_each(|&idx| {
-            // println!("{}", sf.idx);
-            let image = &image_info_list[idx];
+    let options = hash_options(matches);
+    let image_info_list = match hash_inputs(matches, hash_config, cache, journal, &options, &|_| {})
+    {
+        Some(image_info_list) => image_info_list,
+        None => {
+            let index_path = index_path?;
+            return match ImageIndex::load(index_path) {
+                Ok(mut index) => {
+                    if threshold_given && index.group_threshold != threshold {
+                        index.regroup(threshold);
+                    }
+                    Some(index)
+                }
+                Err(err) => {
+                    eprintln!("failed to load index {:?}: {}", index_path, err);
+                    None
+                }
+            };
+        }
+    };
+
+    let existing = index_path
+        .filter(|path| Path::new(path).exists())
+        .and_then(|path| match ImageIndex::load(path) {
+            Ok(index) => match index.config.check_compatible(hash_config) {
+                Ok(()) => Some(index),
+                Err(err) => {
+                    eprintln!("rebuilding index {:?}: {}", path, err);
+                    None
+                }
+            },
+            Err(err) => {
+                eprintln!("rebuilding index {:?}: {}", path, err);
+                None
+            }
+        });
+
+    let index = match existing {
+        Some(mut index) => {
+            let mut image_info_list = image_info_list;
+            if interrupt::requested() {
+                // the scan didn't get everywhere, images it never reached aren't gone
+                let reached = image_info_list
+                    .iter()
+                    .map(|entry| entry.path.clone())
+                    .collect::<HashSet<_>>();
+                image_info_list.extend(
+                    index
+                        .image_info_list
+                        .iter()
+                        .filter(|entry| !reached.contains(&entry.path))
+                        .cloned(),
+                );
+            }
+            println!("updating index...");
+            let (added, removed) = index.update(image_info_list);
+            println!("{} images added, {} images removed", added, removed);
+            if index.group_threshold != threshold {
+                index.regroup(threshold);
+            }
+            index
+        }
+        None => {
+            println!("creating minimum spanning tree...");
+            // spend at most 10 seconds iteratively improving the tour
+            let tour_time = if optimize_tour || index_path.is_some() {
+                10_000
+            } else {
+                0
+            };
+            ImageIndex::build(hash_config.clone(), image_info_list, threshold, tour_time)
+        }
+    };
+
+    save_index(matches, &index);
+    Some(index)
+}
+
+/// saves `index` to `--index` if it was given
+fn save_index(matches: &ArgMatches, index: &ImageIndex) {
+    if let Some(index_path) = matches.value_of("index") {
+        match index.save(index_path) {
+            Ok(()) => println!("index saved to {:?}", index_path),
+            Err(err) => eprintln!("failed to save index {:?}: {}", index_path, err),
+        }
+    }
+}
+
+fn group_images(
+    matches: &ArgMatches,
+    hash_config: &HashConfig,
+    cache: &mut Option<HashCache>,
+    journal: Option<&Journal>,
+) {
+    if let Some(index) = obtain_index(matches, hash_config, cache, journal, true) {
+        materialize(matches, &index);
+    }
+}
+
+/// # Description
+/// symlinks every image of the index into the output directory, named after its position in the tour
+/// ## Comments
+/// symlinks can't point inside an archive, so archive members are extracted as copies
+/// with every archive read once no matter how many of its members are used. images from urls are downloaded
+/// into `DOWNLOADS_DIRECTORY` and linked from there.
+/// whatever an earlier run put in the output directory is replaced, the copies are listed in
+/// `COPIES_MANIFEST` so the next run knows which plain files it may remove
+fn materialize(matches: &ArgMatches, index: &ImageIndex) {
+    let output_directory: &Path = matches.value_of("output").unwrap_or("./sorted").as_ref();
+    let image_info_list = &index.image_info_list;
+    let mut file_name = 0;
+    let mut sym_link_path = PathBuf::new();
+    let mut extractions: HashMap<&Path, HashMap<&str, PathBuf>> = HashMap::new();
+    let mut downloads = vec![];
+
+    let _ = std::fs::create_dir(output_directory);
+    clear_output(output_directory);
+
+    index.circuit.iter().for_each(|&idx| {
+        // println!("{}", sf.idx);
+        let image = &image_info_list[idx];
+        sym_link_path.clear();
+        sym_link_path.push(output_directory);
+        sym_link_path.push(format!("{}", file_name));
+        // the extension of a download or an archive member is only known once its bytes are in
+        if source::is_url(&image.path) {
+            downloads.push((image.path.as_path(), sym_link_path.clone()));
+            file_name += 1;
+            return;
+        }
+        if let Some((archive, inner)) =
+            archive::split_member_path(&image.path).filter(|_| !image.path.exists())
+        {
+            extractions
+                .entry(archive)
+                .or_default()
+                .insert(inner, sym_link_path.clone());
+            file_name += 1;
+            return;
+        }
+        if let Some(ext) = imageformat::extension(&image.path) {
+            sym_link_path.set_extension(ext);
             let absolute_path = image.path.absolutize().unwrap();
-            if let Some(ext) = image.path.extension() {
-                sym_link_path.clear();
-                sym_link_path.push(output_directory);
-                sym_link_path.push(format!("{}", file_name));
-                sym_link_path.set_extension(ext);
-                // println!("{:?} -> {:?}", absolute_path, sym_link_path);
-                std::os::unix::fs::symlink(&absolute_path, &sym_link_path);
-                file_name += 1;
+            // println!("{:?} -> {:?}", absolute_path, sym_link_path);
+            if let Err(err) = std::os::unix::fs::symlink(&absolute_path, &sym_link_path) {
+                eprintln!("failed to link {:?}: {}", sym_link_path, err);
+            }
+            file_name += 1;
+        }
+    });
+
+    if !downloads.is_empty() {
+        link_downloads(output_directory, &downloads);
+    }
+
+    let mut copies = vec![];
+    for (archive, members) in extractions {
+        let extracted = archive::for_each_member(archive, |inner, reader| {
+            if let Some(target) = members.get(inner) {
+                let mut bytes = vec![];
+                let copied = reader.read_to_end(&mut bytes).and_then(|_| {
+                    let target = match imageformat::extension_for_bytes(Path::new(inner), &bytes) {
+                        Some(ext) => target.with_extension(ext),
+                        None => target.clone(),
+                    };
+                    create_copy(&target)?.write_all(&bytes)?;
+                    Ok(target)
+                });
+                match copied {
+                    Ok(target) => copies.push(target),
+                    Err(err) => eprintln!("failed to extract {:?}: {}", target, err),
+                }
+            }
+        });
+        if let Err(err) = extracted {
+            eprintln!("failed to extract from {:?}: {}", archive, err);
+        }
+    }
+
+    if !copies.is_empty() {
+        let mut manifest = vec![];
+        for name in copies.iter().filter_map(|copy| copy.file_name()) {
+            manifest.extend_from_slice(name.as_bytes());
+            manifest.push(b'\n');
+        }
+        let location = output_directory.join(COPIES_MANIFEST);
+        if let Err(err) = fs::write(&location, manifest) {
+            eprintln!("failed to write {:?}: {}", location, err);
+        }
+    }
+}
+
+/// downloads of the url images inside the output directory, named after a hash of their url
+const DOWNLOADS_DIRECTORY: &str = ".image_grouper_downloads";
+
+/// # Description
+/// symlinks every `(url, link)` of `downloads` to its download in `DOWNLOADS_DIRECTORY`
+/// ## Comments
+/// - urls that were downloaded by an earlier run aren't fetched again, so a watch only downloads new urls
+/// - downloads of urls that are no longer in `downloads` are removed
+/// - the link gets the extension of the download, urls without one get it from the downloaded contents
+fn link_downloads(output_directory: &Path, downloads: &[(&Path, PathBuf)]) {
+    let directory = output_directory.join(DOWNLOADS_DIRECTORY);
+    if let Err(err) = fs::create_dir_all(&directory) {
+        eprintln!("failed to create {:?}: {}", directory, err);
+        return;
+    }
+    let key = |url: &Path| blake3::hash(url.as_os_str().as_bytes()).to_hex()[..16].to_string();
+    let mut downloaded = fs::read_dir(&directory)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter_map(|path| Some((path.file_stem()?.to_str()?.to_string(), path)))
+                .collect::<HashMap<_, _>>()
+        })
+        .unwrap_or_default();
+    let wanted = downloads
+        .iter()
+        .map(|(url, _)| key(url))
+        .collect::<HashSet<_>>();
+    downloaded.retain(|key, path| {
+        let stale = !wanted.contains(key);
+        if stale {
+            if let Err(err) = fs::remove_file(&*path) {
+                eprintln!("failed to remove {:?}: {}", path, err);
+            }
+        }
+        !stale
+    });
+
+    downloads.par_iter().for_each(|(url, link)| {
+        let key = key(url);
+        let download = match downloaded.get(&key) {
+            Some(download) => Ok(download.clone()),
+            None => source::download(url).and_then(|bytes| {
+                let mut download = directory.join(&key);
+                if let Some(ext) = imageformat::extension_for_bytes(url, &bytes) {
+                    download.set_extension(ext);
+                }
+                // a download cut short must not pass for a finished one next time
+                let partial = directory.join(format!(".{}.part", key));
+                fs::write(&partial, &bytes)?;
+                fs::rename(&partial, &download)?;
+                Ok(download)
+            }),
+        };
+        let linked = download.and_then(|download| {
+            let link = match download.extension() {
+                Some(ext) => link.with_extension(ext),
+                None => link.clone(),
+            };
+            // relative, so the output directory can be moved as a whole
+            let target = Path::new(DOWNLOADS_DIRECTORY).join(download.file_name().unwrap());
+            std::os::unix::fs::symlink(target, link)
+        });
+        if let Err(err) = linked {
+            eprintln!("failed to download {:?}: {}", url, err);
+        }
+    });
+}
+
+/// never replaces a file, whatever is still in the way after `clear_output` wasn't put there by us
+fn create_copy(target: &Path) -> io::Result<File> {
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target)
+}
+
+/// names the files `materialize` copied into the output directory, one per line
+const COPIES_MANIFEST: &str = ".image_grouper_copies";
+
+/// # Description
+/// removes the links and copies an earlier `materialize` left behind
+/// ## Comments
+/// only symlinks named after a tour position and the plain files listed in `COPIES_MANIFEST` are removed,
+/// so images that were put into the output directory by hand are never touched
+fn clear_output(output_directory: &Path) {
+    let entries = match fs::read_dir(output_directory) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let manifest = output_directory.join(COPIES_MANIFEST);
+    let copies = File::open(&manifest)
+        .and_then(filesysutils::read_path_list)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|copy| copy.file_name().map(PathBuf::from))
+        .collect::<HashSet<_>>();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let numbered = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .is_some_and(|stem| !stem.is_empty() && stem.bytes().all(|b| b.is_ascii_digit()));
+        let ours = entry.file_type().is_ok_and(|kind| {
+            (kind.is_symlink() && numbered)
+                || (kind.is_file() && copies.contains(Path::new(&entry.file_name())))
+        });
+        if ours {
+            if let Err(err) = fs::remove_file(&path) {
+                eprintln!("failed to remove {:?}: {}", path, err);
+            }
+        }
+    }
+    let _ = fs::remove_file(manifest);
+}
+
+/// # Description
+/// groups the directories like the default command, then keeps the output directory and `--index`
+/// in step with them until ctrl-c
+/// ## Comments
+/// new images are spliced into the tour next to their nearest neighbour instead of rebuilding it
+fn watch_images(
+    matches: &ArgMatches,
+    hash_config: &HashConfig,
+    cache: &mut Option<HashCache>,
+    journal: Option<&Journal>,
+) {
+    let mut index = match obtain_index(matches, hash_config, cache, journal, true) {
+        Some(index) => index,
+        None => return,
+    };
+    materialize(matches, &index);
+    if interrupt::requested() {
+        return;
+    }
+
+    let options = hash_options(matches);
+    let debounce = matches
+        .value_of_t::<u64>("debounce")
+        .unwrap_or_else(|err| err.exit());
+    let directories = matches
+        .values_of("directory")
+        .into_iter()
+        .flatten()
+        .map(PathBuf::from)
+        .filter(|directory| directory.is_dir())
+        .collect::<Vec<_>>();
+    let watch = match DirectoryWatch::new(
+        &directories,
+        &options.scan.excluded,
+        Duration::from_millis(debounce),
+    ) {
+        Ok(watch) => watch,
+        Err(err) => {
+            eprintln!("failed to watch {:?}: {}", directories, err);
+            return;
+        }
+    };
+    println!("watching {:?}, ctrl-c to stop", directories);
+
+    while let Some(changed) = watch.next_batch() {
+        if refresh_index(matches, &mut index, &changed, hash_config, cache, &options) {
+            save_index(matches, &index);
+            materialize(matches, &index);
+        }
+    }
+    // once the first grouping is done ctrl-c is how a watch ends, anything cut short is picked up by the next start
+    interrupt::reset();
+}
+
+/// # Description
+/// folds a batch of `changed` paths into `index`
+/// ## Comments
+/// the directories are listed again so removals and the scan filters are handled like in any other run,
+/// but only files under a changed path are hashed, the others keep the hash the index has for them.
+/// an image counts as changed along with its aliases, and new files are checked for byte-identical
+/// copies among the unchanged ones before anything is decoded
+/// ## returns
+/// whether any image was added, removed or got other aliases
+fn refresh_index(
+    matches: &ArgMatches,
+    index: &mut ImageIndex,
+    changed: &HashSet<PathBuf>,
+    hash_config: &HashConfig,
+    cache: &mut Option<HashCache>,
+    options: &HashOptions,
+) -> bool {
+    let files = input_files(matches, options).unwrap_or_default();
+    // the file an entry was read from, archive members come from their archive
+    let file_of = |path: &'_ Path| -> PathBuf {
+        archive::split_member_path(path)
+            .filter(|_| !path.exists())
+            .map_or(path, |(archive, _)| archive)
+            .to_path_buf()
+    };
+    let mut known: HashMap<PathBuf, Vec<&ImageEntry>> = HashMap::new();
+    for entry in &index.image_info_list {
+        known.entry(file_of(&entry.path)).or_default().push(entry);
+    }
+    // copies left behind by an image whose main path is gone have to be hashed under their own name
+    let listed = files
+        .iter()
+        .map(|file| file.path.as_path())
+        .collect::<HashSet<_>>();
+    let mut rehash = index
+        .image_info_list
+        .iter()
+        .filter(|entry| !listed.contains(file_of(&entry.path).as_path()))
+        .flat_map(|entry| entry.aliases.iter().map(|alias| file_of(alias)))
+        .collect::<HashSet<_>>();
+    // so are the copies of a changed image, they only stay merged if their bytes still match
+    rehash.extend(
+        index
+            .image_info_list
+            .iter()
+            .filter(|entry| {
+                std::iter::once(&entry.path)
+                    .chain(&entry.aliases)
+                    .any(|path| watch::is_changed(changed, path))
+            })
+            .flat_map(|entry| {
+                std::iter::once(&entry.path)
+                    .chain(&entry.aliases)
+                    .map(|path| file_of(path))
+            }),
+    );
+
+    let mut current = vec![];
+    let mut to_hash = vec![];
+    let mut unchanged = vec![];
+    let mut kept = 0;
+    // urls aren't watched, their entries stay as they were hashed at startup
+    for (path, entries) in &known {
+        if source::is_url(path) {
+            current.extend(entries.iter().map(|&entry| entry.clone()));
+            kept += 1;
+        }
+    }
+    for file in files {
+        let file_changed = std::iter::once(&file.path)
+            .chain(&file.aliases)
+            .any(|path| watch::is_changed(changed, path));
+        if file_changed || rehash.contains(&file.path) {
+            to_hash.push(file);
+        } else if known.contains_key(&file.path) {
+            unchanged.push(file);
+            kept += 1;
+        }
+    }
+    if to_hash.is_empty() && kept == known.len() {
+        return false;
+    }
+    // new copies of an image the index already has become its aliases, the unchanged files come first
+    // so they keep their place as the main path
+    let mut copies = HashMap::new();
+    if options.exact_duplicates && !to_hash.is_empty() {
+        // what `collapse` adds comes after the links a file already had
+        let links = unchanged
+            .iter()
+            .map(|file| (file.path.clone(), file.aliases.len()))
+            .collect::<HashMap<_, _>>();
+        let (merged, sets) = duplicates::collapse(
+            unchanged.drain(..).chain(to_hash.drain(..)).collect(),
+            cache.as_mut(),
+            &|path| options.may_hash(path),
+        );
+        let copy_count = sets.iter().map(|set| set.paths.len() - 1).sum::<usize>();
+        if copy_count > 0 {
+            println!("{} byte-identical copies merged", copy_count);
+        }
+        for file in merged {
+            match links.get(&file.path) {
+                Some(&links) => {
+                    copies.insert(file.path.clone(), file.aliases[links..].to_vec());
+                    unchanged.push(file);
+                }
+                None => to_hash.push(file),
+            }
+        }
+    }
+    for file in unchanged {
+        let added = copies.remove(&file.path).unwrap_or_default();
+        current.extend(known[&file.path].iter().map(|&entry| {
+            let mut entry = entry.clone();
+            // archive members are copied along with their archive
+            let inner = archive::split_member_path(&entry.path)
+                .filter(|_| entry.path != file.path)
+                .map(|(_, inner)| inner.to_string());
+            entry.aliases.extend(added.iter().map(|copy| match &inner {
+                Some(inner) => archive::member_path(copy, inner),
+                None => copy.clone(),
+            }));
+            entry
+        }));
+    }
+    // the report lists the copies of a full pass, a batch would replace it with just its own
+    let batch_options = HashOptions {
+        duplicate_report: None,
+        // merged above, along with the files that didn't change
+        exact_duplicates: false,
+        ..options.clone()
+    };
+    // the journal is left out, what it recovered at startup may be older than the changed files
+    current.extend(hash_files(
+        to_hash,
+        hash_config,
+        cache,
+        None,
+        &batch_options,
+        &|_| {},
+    ));
+    if interrupt::requested() {
+        return false;
+    }
+
+    let aliases_before = index
+        .image_info_list
+        .iter()
+        .map(|entry| (entry.path.clone(), entry.aliases.clone()))
+        .collect::<HashMap<_, _>>();
+    let (added, removed) = index.update(current);
+    let realiased = index
+        .image_info_list
+        .iter()
+        .filter(|entry| {
+            aliases_before
+                .get(&entry.path)
+                .is_some_and(|aliases| *aliases != entry.aliases)
+        })
+        .count();
+    println!(
+        "{} images added, {} images removed, {} images with changed aliases",
+        added, removed, realiased
+    );
+    added + removed + realiased > 0
+}
+
+/// hashes directories straight into a record file without building anything else
+fn hash_to_records(
+    matches: &ArgMatches,
+    hash_config: &HashConfig,
+    cache: &mut Option<HashCache>,
+    journal: Option<&Journal>,
+) {
+    let output_path = matches.value_of("output").expect("output is required");
+    let format = match record_format(matches, Path::new(output_path)) {
+        Some(format) => format,
+        None => return,
+    };
+    let writer =
+        File::create(output_path).and_then(|file| RecordWriter::new(format, BufWriter::new(file)));
+    let writer = match writer {
+        Ok(writer) => Mutex::new(writer),
+        Err(err) => {
+            eprintln!("failed to create {:?}: {}", output_path, err);
+            return;
+        }
+    };
+
+    let options = hash_options(matches);
+    // one record per path, aliases included
+    let write_record = |entry: &ImageEntry| {
+        let mut writer = writer.lock().unwrap();
+        for path in std::iter::once(&entry.path).chain(&entry.aliases) {
+            let record = HashRecord::new(
+                &ImageEntry {
+                    hash: entry.hash,
+                    path: path.clone(),
+                    aliases: vec![],
+                },
+                hash_config,
+            );
+            if let Err(err) = writer.write(&record) {
+                eprintln!("failed to write record for {:?}: {}", path, err);
             }
+        }
+    };
+    let image_info_list = match hash_inputs(
+        matches,
+        hash_config,
+        cache,
+        journal,
+        &options,
+        &write_record,
+    ) {
+        Some(image_info_list) => image_info_list,
+        None => {
+            eprintln!("nothing to hash, give directories, -i images, --files-from or --urls");
+            return;
+        }
+    };
+
+    match writer.into_inner().unwrap().flush() {
+        Ok(()) => println!(
+            "{} hashes written to {:?}",
+            image_info_list
+                .iter()
+                .map(|entry| 1 + entry.aliases.len())
+                .sum::<usize>(),
+            output_path
+        ),
+        Err(err) => eprintln!("failed to write {:?}: {}", output_path, err),
+    }
+}
+
+/// groups hashes read from record files, the images themselves are never opened
+fn import_records(matches: &ArgMatches) {
+    let format = match matches.value_of("format") {
+        Some(name) => match RecordFormat::from_name(name) {
+            Some(format) => Some(format),
+            None => {
+                eprintln!("unknown record format '{}'", name);
+                return;
+            }
+        },
+        None => None,
+    };
+    let paths = matches
+        .values_of("records")
+        .expect("records are required")
+        .map(Path::new);
+
+    let (config, image_info_list) =
+        match records::collect_entries(records::open_record_files(paths, format)) {
+            Ok(Some(imported)) => imported,
+            Ok(None) => {
+                eprintln!("no records to import");
+                return;
+            }
+            Err(err) => {
+                eprintln!("failed to import records: {}", err);
+                return;
+            }
+        };
+    println!("{} hashes imported", image_info_list.len());
+
+    let threshold = matches
+        .value_of_t::<u64>("threshold")
+        .unwrap_or_else(|err| err.exit());
+    println!("creating minimum spanning tree...");
+    let index = ImageIndex::build(config, image_info_list, threshold, 10_000);
+    save_index(matches, &index);
+    materialize(matches, &index);
+}
+
+/// # Description
+/// merges hashes computed elsewhere and groups the union
+/// ## Comments
+/// caches can hold several configs, only the hashes matching the config of the earlier inputs
+/// (or the command line config if a cache comes first) are taken from them
+fn merge_inputs(matches: &ArgMatches, hash_config: &HashConfig) {
+    let mut remaps = vec![];
+    for spec in matches.values_of("remap").into_iter().flatten() {
+        match PathRemap::parse(spec) {
+            Some(remap) => remaps.push(remap),
+            None => {
+                eprintln!("invalid remapping '{}', expected FROM=TO", spec);
+                return;
+            }
+        }
+    }
+
+    let mut merger = Merger::new(remaps);
+    for input in matches.values_of("inputs").expect("inputs are required") {
+        let source = if let Some(format) = RecordFormat::from_path(input) {
+            File::open(input)
+                .and_then(|file| {
+                    records::collect_entries(records::read_records(format, BufReader::new(file)))
+                })
+                .map(|source| source.unwrap_or_else(|| (hash_config.clone(), vec![])))
+        } else {
+            ImageIndex::load(input)
+                .map(|index| (index.config, index.image_info_list))
+                .or_else(|index_err| {
+                    if !Path::new(input).exists() {
+                        return Err(index_err);
+                    }
+                    let cache = HashCache::open(input).map_err(|_| index_err)?;
+                    let config = merger.config().unwrap_or(hash_config).clone();
+                    let entries = cache.entries_for(&config);
+                    Ok((config, entries))
+                })
+        };
+
+        let result = source.and_then(|(config, entries)| {
+            let count = entries.len();
+            merger.add(&config, entries).map(|_| count)
         });
+        match result {
+            Ok(count) => println!("{} hashes read from {:?}", count, input),
+            Err(err) => {
+                eprintln!("failed to merge {:?}: {}", input, err);
+                return;
+            }
+        }
+    }
+
+    let (config, image_info_list, report) = match merger.finish() {
+        Some(merged) => merged,
+        None => return,
+    };
+    println!(
+        "{} images merged from {} inputs, {} duplicate paths ({} with different hashes)",
+        image_info_list.len(),
+        report.sources,
+        report.duplicates,
+        report.conflicts
+    );
+
+    let threshold = matches
+        .value_of_t::<u64>("threshold")
+        .unwrap_or_else(|err| err.exit());
+    println!("creating minimum spanning tree...");
+    let index = ImageIndex::build(config, image_info_list, threshold, 10_000);
+    save_index(matches, &index);
+    materialize(matches, &index);
+}
+
+/// `--format` if given, otherwise guessed from the extension of `path`
+fn record_format(matches: &ArgMatches, path: &Path) -> Option<RecordFormat> {
+    let format = match matches.value_of("format") {
+        Some(name) => RecordFormat::from_name(name),
+        None => RecordFormat::from_path(path),
+    };
+    if format.is_none() {
+        eprintln!("unknown record format, use --format csv or --format ndjson");
+    }
+    format
+}
+
+fn export_graph(
+    matches: &ArgMatches,
+    hash_config: &HashConfig,
+    cache: &mut Option<HashCache>,
+    journal: Option<&Journal>,
+) {
+    let format_name = matches.value_of("format").unwrap_or("dot");
+    let format = match ExportFormat::from_name(format_name) {
+        Some(format) => format,
+        None => {
+            eprintln!("unknown export format '{}'", format_name);
+            return;
+        }
+    };
+    let output_path = matches.value_of("output").expect("output is required");
+
+    let index = match obtain_index(matches, hash_config, cache, journal, false) {
+        Some(index) => index,
+        None => {
+            eprintln!("nothing to export, give directories or an --index");
+            return;
+        }
+    };
+    if format == ExportFormat::Sqlite {
+        match sqlite::write_sqlite(&index, output_path) {
+            Ok(()) => println!("database written to {:?}", output_path),
+            Err(err) => eprintln!("failed to write {:?}: {}", output_path, err),
+        }
+        return;
+    }
+    let groups = index
+        .group_table
+        .iter()
+        .flat_map(|(&group, info)| {
+            info.similar_images
+                .iter()
+                .map(move |image| (image.image_idx, group))
+        })
+        .collect::<HashMap<_, _>>();
+
+    let image_info_list = &index.image_info_list;
+    let export = if matches.is_present("knn") {
+        let k = matches
+            .value_of_t::<usize>("knn")
+            .unwrap_or_else(|err| err.exit());
+        println!("creating {}-nearest-neighbour graph...", k);
+        let knn = KnnGraph::new(image_info_list, k);
+        GraphExport::new(&index.config, image_info_list, knn.edges(), &groups)
+    } else {
+        GraphExport::new(
+            &index.config,
+            image_info_list,
+            index.mst.iter().flat_map(|mst| mst.edges()),
+            &groups,
+        )
+    };
+
+    let result = File::create(output_path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        export.write(format, &mut writer)?;
+        writer.flush()
+    });
+    match result {
+        Ok(()) => println!("graph written to {:?}", output_path),
+        Err(err) => eprintln!("failed to write {:?}: {}", output_path, err),
+    }
+}
+
+fn query_images(
+    matches: &ArgMatches,
+    hash_config: &HashConfig,
+    cache: &mut Option<HashCache>,
+    journal: Option<&Journal>,
+) {
+    let k = matches
+        .value_of_t::<usize>("count")
+        .unwrap_or_else(|err| err.exit());
+
+    // a bare directory scan doesn't need the spanning tree, anything involving an index does
+    let options = hash_options(matches);
+    let image_info_list = if matches.is_present("index") {
+        None
+    } else {
+        hash_inputs(matches, hash_config, cache, journal, &options, &|_| {})
+    };
+    let (hash_config, image_info_list) = match image_info_list {
+        Some(image_info_list) => (hash_config.clone(), image_info_list),
+        _ => match obtain_index(matches, hash_config, cache, journal, false) {
+            Some(index) => (index.config, index.image_info_list),
+            None => {
+                eprintln!(
+                    "nothing to search, give -d directories, -i images, --urls or an --index"
+                );
+                return;
+            }
+        },
+    };
+    let index = ImageQuery::new(&image_info_list);
+
+    for query_path in matches.values_of("image").expect("image is required") {
+        match index.top_k_for_path(query_path, &hash_config, k) {
+            Ok(matches) => {
+                println!("{}:", query_path);
+                for m in matches {
+                    println!(
+                        "    distance = {:3}, similarity = {:3}, {:?}",
+                        m.distance, m.similarity, image_info_list[m.image_idx].path
+                    );
+                }
+            }
+            Err(err) => eprintln!("failed to query {:?}: {}", query_path, err),
+        }
     }
 }
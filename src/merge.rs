@@ -0,0 +1,173 @@
+//! combines hashes computed on different machines into a single list that can be grouped
+use super::*;
+use crate::perceptual::HashConfig;
+use std::io;
+
+/// # Description
+/// rewrites paths starting with `from` to start with `to` instead
+/// ## Comments
+/// used to line up the mount points of the same share on different machines, e.g. `/mnt/nas=/Volumes/nas`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PathRemap {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+impl PathRemap {
+    /// parses `FROM=TO`
+    pub fn parse(spec: &str) -> Option<Self> {
+        let (from, to) = spec.split_once('=')?;
+        if from.is_empty() {
+            return None;
+        }
+        Some(Self {
+            from: PathBuf::from(from),
+            to: PathBuf::from(to),
+        })
+    }
+
+    /// `None` if `path` doesn't start with `from`, prefixes are matched whole components at a time
+    pub fn apply(&self, path: &Path) -> Option<PathBuf> {
+        path.strip_prefix(&self.from)
+            .ok()
+            .map(|rest| self.to.join(rest))
+    }
+}
+
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct MergeReport {
+    pub sources: usize,
+    /// paths seen in more than one source, after remapping
+    pub duplicates: usize,
+    /// duplicates whose hashes disagree
+    pub conflicts: usize,
+}
+
+/// # Description
+/// accumulates entries from several sources
+/// ## Comments
+/// the first remap matching a path is applied, when a path shows up more than once the earliest
+/// source wins, so sources should be added from most to least trusted
+pub struct Merger {
+    remaps: Vec<PathRemap>,
+    config: Option<HashConfig>,
+    entries: Vec<ImageEntry>,
+    positions: HashMap<PathBuf, usize>,
+    report: MergeReport,
+}
+impl Merger {
+    pub fn new(remaps: Vec<PathRemap>) -> Self {
+        Self {
+            remaps,
+            config: None,
+            entries: vec![],
+            positions: HashMap::new(),
+            report: MergeReport::default(),
+        }
+    }
+
+    /// config of the first source, every later source has to match it
+    pub fn config(&self) -> Option<&HashConfig> {
+        self.config.as_ref()
+    }
+
+    pub fn remap(&self, path: &Path) -> PathBuf {
+        self.remaps
+            .iter()
+            .find_map(|remap| remap.apply(path))
+            .unwrap_or_else(|| path.to_path_buf())
+    }
+
+    /// # Description
+    /// adds every entry of one source
+    /// ## returns
+    /// an `InvalidData` error, without adding anything, if `config` isn't compatible with the earlier sources
+    pub fn add<I>(&mut self, config: &HashConfig, entries: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = ImageEntry>,
+    {
+        match &self.config {
+            Some(merged_config) => merged_config.check_compatible(config)?,
+            None => self.config = Some(config.clone()),
+        }
+        self.report.sources += 1;
+
+        for entry in entries {
+            let path = self.remap(&entry.path);
+            match self.positions.get(&path) {
+                Some(&pos) => {
+                    self.report.duplicates += 1;
+                    if self.entries[pos].hash != entry.hash {
+                        self.report.conflicts += 1;
+                    }
+                }
+                None => {
+                    self.positions.insert(path.clone(), self.entries.len());
+                    let aliases = entry
+                        .aliases
+                        .iter()
+                        .map(|alias| self.remap(alias))
+                        .collect();
+                    self.entries.push(ImageEntry {
+                        hash: entry.hash,
+                        path,
+                        aliases,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `None` if no source was added
+    pub fn finish(self) -> Option<(HashConfig, Vec<ImageEntry>, MergeReport)> {
+        let config = self.config?;
+        Some((config, self.entries, self.report))
+    }
+}
+
+#[test]
+fn merge_remaps_and_rejects() {
+    let entry = |path: &str, hash: u64| ImageEntry {
+        hash,
+        path: PathBuf::from(path),
+        aliases: vec![],
+    };
+    let remaps = ["/mnt/nas=/data", "/Volumes/nas=/data"]
+        .iter()
+        .map(|spec| PathRemap::parse(spec).unwrap())
+        .collect();
+    let config = HashConfig::default();
+
+    let mut merger = Merger::new(remaps);
+    merger
+        .add(
+            &config,
+            vec![entry("/mnt/nas/a.png", 1), entry("/mnt/nasty/b.png", 2)],
+        )
+        .unwrap();
+    merger
+        .add(
+            &config,
+            vec![entry("/Volumes/nas/a.png", 3), entry("/home/c.png", 4)],
+        )
+        .unwrap();
+    assert!(merger
+        .add(&HashConfig::new(HashType::DHASH), vec![entry("/d.png", 5)])
+        .is_err());
+
+    let (_, entries, report) = merger.finish().unwrap();
+    let paths = entries
+        .iter()
+        .map(|e| e.path.to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["/data/a.png", "/mnt/nasty/b.png", "/home/c.png"]);
+    assert_eq!(entries[0].hash, 1);
+    assert_eq!(
+        report,
+        MergeReport {
+            sources: 2,
+            duplicates: 1,
+            conflicts: 1
+        }
+    );
+}
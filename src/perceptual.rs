@@ -1,19 +1,200 @@
+use crate::HashType;
 use image::{imageops::FilterType, GenericImageView};
+use serde::{Deserialize, Serialize};
+use std::{fmt, io};
+
+/// hashes are packed into a `u64`, so the grid can be at most 8x8
+pub const MAX_GRID_SIZE: u32 = 8;
+
+/// serializable mirror of `image::imageops::FilterType`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    #[default]
+    Gaussian,
+    Lanczos3,
+}
+impl ResizeFilter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "nearest" => Some(Self::Nearest),
+            "triangle" => Some(Self::Triangle),
+            "catmullrom" => Some(Self::CatmullRom),
+            "gaussian" => Some(Self::Gaussian),
+            "lanczos3" => Some(Self::Lanczos3),
+            _ => None,
+        }
+    }
+
+    /// the name used on the command line and in serialized data
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Nearest => "nearest",
+            Self::Triangle => "triangle",
+            Self::CatmullRom => "catmullrom",
+            Self::Gaussian => "gaussian",
+            Self::Lanczos3 => "lanczos3",
+        }
+    }
+}
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// # Description
+/// everything that influences the value of a hash
+/// ## Comments
+/// hashes are only comparable when they were produced by equal configs, so every cached or
+/// exported hash carries the config that produced it
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct HashConfig {
+    pub algorithm: HashType,
+    /// the image is downscaled to `grid_size` x `grid_size` cells, one bit per cell
+    pub grid_size: u32,
+    pub filter: ResizeFilter,
+    /// version of image_grouper that produced the hashes
+    pub version: String,
+}
+impl HashConfig {
+    /// the defaults this crate has always used: 8x8 grid and a gaussian filter
+    pub fn new(algorithm: HashType) -> Self {
+        Self {
+            algorithm,
+            grid_size: MAX_GRID_SIZE,
+            filter: ResizeFilter::default(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+
+    /// # Description
+    /// hashes `image` with this configuration
+    /// ## returns
+    /// `None` if the algorithm isn't implemented yet or the grid size doesn't fit in 64 bits
+    pub fn hash(&self, image: &image::DynamicImage) -> Option<u64> {
+        if self.grid_size == 0 || self.grid_size > MAX_GRID_SIZE {
+            return None;
+        }
+        let filter = self.filter.into();
+        match self.algorithm {
+            HashType::AHASH => Some(ahash_with(image, self.grid_size, filter)),
+            HashType::DHASH => Some(dhash_with(image, self.grid_size, filter)),
+            HashType::PHASH => None,
+        }
+    }
+
+    /// # Description
+    /// checks that hashes made with `other` can be compared with hashes made with `self`
+    /// ## returns
+    /// an `InvalidData` error describing the first mismatch
+    pub fn check_compatible(&self, other: &Self) -> io::Result<()> {
+        let mismatch = if self.algorithm != other.algorithm {
+            Some("algorithm")
+        } else if self.grid_size != other.grid_size {
+            Some("grid size")
+        } else if self.filter != other.filter {
+            Some("resize filter")
+        } else if self.version != other.version {
+            Some("version")
+        } else {
+            None
+        };
+        match mismatch {
+            Some(field) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "incompatible hashes, {} differs: [{}] vs [{}]",
+                    field, self, other
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+impl HashConfig {
+    /// # Description
+    /// compact single field form, `algorithm:grid_size:filter:version`
+    /// ## Comments
+    /// used where a whole struct per hash would be too heavy, like csv columns
+    pub fn tag(&self) -> String {
+        format!(
+            "{}:{}:{}:{}",
+            self.algorithm.name(),
+            self.grid_size,
+            self.filter.name(),
+            self.version
+        )
+    }
+
+    /// parses the output of `tag`, a bare algorithm name means the default parameters
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let mut fields = tag.splitn(4, ':');
+        let mut config = Self::new(HashType::from_name(fields.next()?)?);
+        if let Some(grid_size) = fields.next() {
+            config.grid_size = grid_size.parse().ok()?;
+            config.filter = ResizeFilter::from_name(fields.next()?)?;
+            config.version = fields.next()?.to_string();
+        }
+        Some(config)
+    }
+}
+impl Default for HashConfig {
+    fn default() -> Self {
+        Self::new(HashType::default())
+    }
+}
+impl fmt::Display for HashConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}x{} {} v{}",
+            self.algorithm.name(),
+            self.grid_size,
+            self.grid_size,
+            self.filter.name(),
+            self.version
+        )
+    }
+}
+
+/// # Description
+/// hashes `image` with the selected hash function and the default config
+/// ## returns
+/// `None` if the hash function isn't implemented yet
+pub fn hash(image: &image::DynamicImage, method: HashType) -> Option<u64> {
+    HashConfig::new(method).hash(image)
+}
+
 /// average hashing
 pub fn ahash(image: &image::DynamicImage) -> u64 {
-    let downscaled = image.resize_exact(8, 8, FilterType::Gaussian).grayscale();
+    ahash_with(image, MAX_GRID_SIZE, FilterType::Gaussian)
+}
+
+/// average hashing over a `grid` x `grid` downscale
+pub fn ahash_with(image: &image::DynamicImage, grid: u32, filter: FilterType) -> u64 {
+    let downscaled = image.resize_exact(grid, grid, filter).grayscale();
 
     let average = downscaled
         .pixels()
         .map(|(_, _, e)| e[0] as u64)
         .sum::<u64>()
-        / 64;
+        / (grid * grid) as u64;
 
     let mut hash = 0;
-    for i in 0..8 {
-        for j in 0..8 {
+    for i in 0..grid {
+        for j in 0..grid {
             let a = downscaled.get_pixel(j, i)[0] as u64;
-            hash |= ((a > average) as u64) << (j + i * 8);
+            hash |= ((a > average) as u64) << (j + i * grid);
         }
     }
     hash
@@ -21,19 +202,24 @@ pub fn ahash(image: &image::DynamicImage) -> u64 {
 /// difference hashing
 /// source: https://web.archive.org/web/20210806051726/https://people.cs.umass.edu/~liberato/courses/2020-spring-compsci590k/lectures/09-perceptual-hashing/
 pub fn dhash(image: &image::DynamicImage) -> u64 {
-    let downscaled = image.resize_exact(9, 8, FilterType::Gaussian).grayscale();
+    dhash_with(image, MAX_GRID_SIZE, FilterType::Gaussian)
+}
+
+/// difference hashing over a `grid + 1` x `grid` downscale
+pub fn dhash_with(image: &image::DynamicImage, grid: u32, filter: FilterType) -> u64 {
+    let downscaled = image.resize_exact(grid + 1, grid, filter).grayscale();
     let mut hash = 0;
-    for i in 0..8 {
-        for j in 0..8 {
+    for i in 0..grid {
+        for j in 0..grid {
             let a = downscaled.get_pixel(j, i)[0];
-            let b = downscaled.get_pixel(j+1, i)[0];
-            hash |= ((a < b) as u64) << (j + i * 8);
+            let b = downscaled.get_pixel(j + 1, i)[0];
+            hash |= ((a < b) as u64) << (j + i * grid);
         }
     }
     hash
 }
 
-pub fn phash(image: &image::DynamicImage) -> u64 {
+pub fn phash(_image: &image::DynamicImage) -> u64 {
     unimplemented!("p-hash currently not implemented")
 }
 
@@ -42,7 +228,15 @@ pub fn phash(image: &image::DynamicImage) -> u64 {
 /// ## returns
 /// a value of 0-100. where 0 means no similarity and 100 meaning very similar
 pub fn similarity_score(hash_a: u64, hash_b: u64) -> u64 {
-    ((hash_a ^ hash_b).count_zeros() as u64 * 100) /  64
+    ((hash_a ^ hash_b).count_zeros() as u64 * 100) / 64
+}
+
+/// # Description
+/// number of bits that differ between two hashes
+/// ## Comments
+/// unlike `hamming_distance` this isn't rescaled, so it satisfies the triangle inequality
+pub fn bit_distance(hash_a: u64, hash_b: u64) -> u32 {
+    (hash_a ^ hash_b).count_ones()
 }
 
 /// # Description
@@ -50,17 +244,17 @@ pub fn similarity_score(hash_a: u64, hash_b: u64) -> u64 {
 /// ## returns
 /// a value of 0-100. where 0 means close and 100 meaning very far
 pub fn hamming_distance(hash_a: u64, hash_b: u64) -> u64 {
-    ((hash_a ^ hash_b).count_ones() as u64 * 100) /  64
+    ((hash_a ^ hash_b).count_ones() as u64 * 100) / 64
 }
 
 #[test]
 fn ahash_sanity() {
-    let i1 = image::open("/home/narco/Pictures/memes/pepe/1566100937558.png").unwrap();
-    let i2 = image::open("/home/narco/Pictures/memes/pepe/1566100937558_c.png").unwrap();
+    let i1 = image::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test_set/a.png")).unwrap();
+    let i2 = image::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test_set/c.png")).unwrap();
     let a = ahash(&i1);
     let b = ahash(&i2);
     let score = similarity_score(a, b);
-    println!("a = {:08x}",a);
-    println!("b = {:08x}",b);
-    println!("score = {}",score);
+    println!("a = {:08x}", a);
+    println!("b = {:08x}", b);
+    println!("score = {}", score);
 }
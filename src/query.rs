@@ -0,0 +1,105 @@
+//! answers "which images look like this one?" without building the whole grouping
+use super::*;
+use crate::{bktree::BKTree, perceptual::HashConfig};
+use std::io;
+
+#[derive(Clone, Serialize, Debug, PartialEq, Eq)]
+pub struct QueryMatch {
+    pub image_idx: usize,
+    /// `perceptual::hamming_distance`, 0 means identical hashes
+    pub distance: u64,
+    /// `perceptual::similarity_score`, 100 means identical hashes
+    pub similarity: u64,
+}
+
+/// # Description
+/// index over hashed images that can be queried repeatedly
+/// ## Comments
+/// match indices refer to positions in the `entries` the index was built from
+pub struct ImageQuery<'a> {
+    entries: &'a [ImageEntry],
+    tree: BKTree,
+}
+impl<'a> ImageQuery<'a> {
+    pub fn new(entries: &'a [ImageEntry]) -> Self {
+        Self {
+            entries,
+            tree: BKTree::from_entries(entries),
+        }
+    }
+
+    /// # Description
+    /// the `k` images closest to `hash`
+    /// ## returns
+    /// matches sorted from most to least similar
+    pub fn top_k(&self, hash: u64, k: usize) -> Vec<QueryMatch> {
+        self.tree
+            .nearest(hash, k, |_| true)
+            .into_iter()
+            .map(|(image_idx, _)| {
+                let other = self.entries[image_idx].hash;
+                QueryMatch {
+                    image_idx,
+                    distance: perceptual::hamming_distance(hash, other),
+                    similarity: perceptual::similarity_score(hash, other),
+                }
+            })
+            .collect()
+    }
+
+    /// # Description
+    /// hashes the image at `path` with `config` and looks up its `k` closest images
+    /// ## Comments
+    /// `config` has to be the config the indexed entries were hashed with
+    /// ## returns
+    /// an error if the image can't be decoded or `config` can't be hashed with
+    pub fn top_k_for_path<P: AsRef<Path>>(
+        &self,
+        path: P,
+        config: &HashConfig,
+        k: usize,
+    ) -> io::Result<Vec<QueryMatch>> {
+        let image = imageformat::open(path.as_ref())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let hash = config.hash(&image).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("[{}] not implemented", config),
+            )
+        })?;
+        Ok(self.top_k(hash, k))
+    }
+}
+
+/// one-off version of `ImageQuery::top_k`
+pub fn query(entries: &[ImageEntry], hash: u64, k: usize) -> Vec<QueryMatch> {
+    ImageQuery::new(entries).top_k(hash, k)
+}
+
+#[test]
+fn query_ranks_closest_first() {
+    let entries = [0b1111u64, 0, 0b1, u64::MAX]
+        .iter()
+        .map(|&hash| ImageEntry {
+            hash,
+            path: PathBuf::new(),
+            aliases: vec![],
+        })
+        .collect::<Vec<_>>();
+    let matches = query(&entries, 0, 3);
+    assert_eq!(
+        matches.iter().map(|m| m.image_idx).collect::<Vec<_>>(),
+        vec![1, 2, 0]
+    );
+    assert_eq!(matches[0].distance, 0);
+    assert_eq!(matches[0].similarity, 100);
+
+    let matches = ImageQuery::new(&entries)
+        .top_k_for_path(
+            concat!(env!("CARGO_MANIFEST_DIR"), "/test_set/a.png"),
+            &HashConfig::default(),
+            1,
+        )
+        .unwrap();
+    assert_eq!(matches.len(), 1);
+}
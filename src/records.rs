@@ -0,0 +1,278 @@
+//! plain `path,algorithm,hash` records, so hashing can happen on several machines and the
+//! concatenated results can be grouped without decoding the images again
+use super::*;
+use crate::perceptual::HashConfig;
+use std::io::{self, BufRead, Read, Write};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    Csv,
+    /// newline delimited json, one record per line
+    Ndjson,
+}
+impl RecordFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(Self::Csv),
+            "ndjson" | "jsonl" => Some(Self::Ndjson),
+            _ => None,
+        }
+    }
+
+    /// guesses the format from a file extension
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        Self::from_name(path.as_ref().extension()?.to_str()?)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct HashRecord {
+    pub path: PathBuf,
+    /// the full config that produced `hash` in `HashConfig::tag` form
+    #[serde(with = "config_tag")]
+    pub algorithm: HashConfig,
+    #[serde(with = "hexhash")]
+    pub hash: u64,
+}
+impl HashRecord {
+    pub fn new(entry: &ImageEntry, config: &HashConfig) -> Self {
+        Self {
+            path: entry.path.clone(),
+            algorithm: config.clone(),
+            hash: entry.hash,
+        }
+    }
+}
+
+mod config_tag {
+    use super::*;
+    use serde::{de::Error, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(config: &HashConfig, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&config.tag())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashConfig, D::Error> {
+        let tag = String::deserialize(deserializer)?;
+        HashConfig::from_tag(&tag)
+            .ok_or_else(|| D::Error::custom(format!("unknown hash algorithm '{}'", tag)))
+    }
+}
+
+const CSV_HEADER: [&str; 3] = ["path", "algorithm", "hash_hex"];
+/// header of csv files written before hashes became hex, their hash column is decimal
+const LEGACY_CSV_HEADER: [&str; 3] = ["path", "algorithm", "hash"];
+
+/// writes records one at a time, nothing is buffered beyond the underlying writer
+pub enum RecordWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    Ndjson(W),
+}
+impl<W: Write> RecordWriter<W> {
+    pub fn new(format: RecordFormat, writer: W) -> io::Result<Self> {
+        match format {
+            RecordFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(writer);
+                writer.write_record(CSV_HEADER)?;
+                Ok(Self::Csv(Box::new(writer)))
+            }
+            RecordFormat::Ndjson => Ok(Self::Ndjson(writer)),
+        }
+    }
+
+    pub fn write(&mut self, record: &HashRecord) -> io::Result<()> {
+        match self {
+            Self::Csv(writer) => writer.serialize(record).map_err(io::Error::from),
+            Self::Ndjson(writer) => {
+                serde_json::to_writer(&mut *writer, record)?;
+                writer.write_all(b"\n")
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Csv(writer) => writer.flush(),
+            Self::Ndjson(writer) => writer.flush(),
+        }
+    }
+}
+
+/// # Description
+/// lazily parses records from `reader`
+/// ## Comments
+/// csv headers are skipped wherever they appear, so files concatenated with `cat` read fine.
+/// the last header seen decides how the hash column is read, rows before any header are hex
+pub fn read_records<'a, R: BufRead + 'a>(
+    format: RecordFormat,
+    reader: R,
+) -> Box<dyn Iterator<Item = io::Result<HashRecord>> + 'a> {
+    match format {
+        RecordFormat::Csv => {
+            let mut hex = true;
+            Box::new(
+                csv::ReaderBuilder::new()
+                    .has_headers(false)
+                    .from_reader(reader)
+                    .into_records()
+                    .filter_map(move |row| {
+                        let row = match row {
+                            Ok(row) => row,
+                            Err(err) => return Some(Err(err.into())),
+                        };
+                        if row.iter().eq(CSV_HEADER) {
+                            hex = true;
+                        } else if row.iter().eq(LEGACY_CSV_HEADER) {
+                            hex = false;
+                        } else {
+                            return Some(parse_csv_row(&row, hex));
+                        }
+                        None
+                    }),
+            )
+        }
+        RecordFormat::Ndjson => Box::new(
+            reader
+                .lines()
+                .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(|line| Ok(serde_json::from_str(&line?)?)),
+        ),
+    }
+}
+
+/// # Description
+/// csv can't tell strings from numbers, so the header decides whether the hash column is hex or decimal
+/// ## Comments
+/// letting serde guess would turn a hex hash made only of digits into a decimal number
+fn parse_csv_row(row: &csv::StringRecord, hex: bool) -> io::Result<HashRecord> {
+    let invalid = |what: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid {} in csv row {:?}", what, row),
+        )
+    };
+    if row.len() != CSV_HEADER.len() {
+        return Err(invalid("column count"));
+    }
+    Ok(HashRecord {
+        path: PathBuf::from(&row[0]),
+        algorithm: HashConfig::from_tag(&row[1]).ok_or_else(|| invalid("algorithm"))?,
+        hash: if hex {
+            hexhash::from_hex(&row[2])
+        } else {
+            row[2].parse().ok()
+        }
+        .ok_or_else(|| invalid("hash"))?,
+    })
+}
+
+/// # Description
+/// turns records into entries that can be grouped, duplicate paths keep their first record
+/// ## returns
+/// an `InvalidData` error if the records were made with incompatible hash configs
+pub fn collect_entries<I>(records: I) -> io::Result<Option<(HashConfig, Vec<ImageEntry>)>>
+where
+    I: Iterator<Item = io::Result<HashRecord>>,
+{
+    let mut config: Option<HashConfig> = None;
+    let mut seen = HashSet::new();
+    let mut entries = vec![];
+    for record in records {
+        let record = record?;
+        match &config {
+            Some(config) => config.check_compatible(&record.algorithm)?,
+            None => config = Some(record.algorithm.clone()),
+        }
+        if seen.insert(record.path.clone()) {
+            entries.push(ImageEntry {
+                hash: record.hash,
+                path: record.path,
+                aliases: vec![],
+            });
+        }
+    }
+    Ok(config.map(|config| (config, entries)))
+}
+
+/// opens every record file, `-` is stdin, the format is guessed from the extension unless given
+pub fn open_record_files<'a>(
+    paths: impl Iterator<Item = &'a Path> + 'a,
+    format: Option<RecordFormat>,
+) -> impl Iterator<Item = io::Result<HashRecord>> + 'a {
+    paths.flat_map(move |path| {
+        let format = format.or_else(|| RecordFormat::from_path(path));
+        let format = match format {
+            Some(format) => format,
+            None => {
+                let err = io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("can't tell the record format of {:?}", path),
+                );
+                return Box::new(std::iter::once(Err(err))) as Box<dyn Iterator<Item = _>>;
+            }
+        };
+        let reader: Box<dyn Read> = if path == Path::new("-") {
+            Box::new(io::stdin())
+        } else {
+            match std::fs::File::open(path) {
+                Ok(file) => Box::new(file),
+                Err(err) => return Box::new(std::iter::once(Err(err))),
+            }
+        };
+        read_records(format, io::BufReader::new(reader))
+    })
+}
+
+#[test]
+fn records_round_trip() {
+    let config = HashConfig::default();
+    let entries = [
+        ImageEntry {
+            hash: u64::MAX,
+            path: PathBuf::from("a, \"weird\" name.png"),
+            aliases: vec![],
+        },
+        ImageEntry {
+            // only decimal digits once written as hex
+            hash: 0x1234_5678_9012_3456,
+            path: PathBuf::from("b.png"),
+            aliases: vec![],
+        },
+    ];
+
+    for format in [RecordFormat::Csv, RecordFormat::Ndjson] {
+        let mut buffer = vec![];
+        // two writers back to back, like files concatenated with cat
+        for entry in &entries {
+            let mut writer = RecordWriter::new(format, &mut buffer).unwrap();
+            writer.write(&HashRecord::new(entry, &config)).unwrap();
+            writer.flush().unwrap();
+        }
+
+        let (read_config, read_entries) = collect_entries(read_records(format, &buffer[..]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(read_config, config);
+        assert_eq!(read_entries.len(), 2);
+        assert_eq!(read_entries[0].path, entries[0].path);
+        assert_eq!(read_entries[0].hash, u64::MAX);
+        assert_eq!(read_entries[1].hash, 0x1234_5678_9012_3456);
+    }
+
+    // written before hashes became hex, followed by a current file
+    let legacy = format!(
+        "path,algorithm,hash\na.png,{tag},1234567890123456\npath,algorithm,hash_hex\nb.png,{tag},1234567890123456\n",
+        tag = config.tag()
+    );
+    let read = read_records(RecordFormat::Csv, legacy.as_bytes())
+        .map(|record| record.unwrap().hash)
+        .collect::<Vec<_>>();
+    assert_eq!(read, [1234567890123456, 0x1234_5678_9012_3456]);
+
+    let mut other = HashRecord::new(&entries[1], &HashConfig::new(HashType::DHASH));
+    other.path = PathBuf::from("c.png");
+    let mixed = vec![Ok(HashRecord::new(&entries[0], &config)), Ok(other)];
+    assert!(collect_entries(mixed.into_iter()).is_err());
+}
@@ -0,0 +1,238 @@
+//! where images come from: local directories, explicit lists of files or urls served over http(s)
+use super::*;
+use crate::{
+    filesysutils::{self, FileId, ScanOptions},
+    perceptual::HashConfig,
+};
+use image::DynamicImage;
+use rayon::prelude::*;
+use std::{
+    fs,
+    io::{self, BufRead},
+    sync::OnceLock,
+    time::Duration,
+};
+
+/// # Description
+/// a collection of images that can be listed and fetched
+/// ## Comments
+/// items double as the stable identifier of an image, they are what ends up in `ImageEntry::path`
+pub trait ImageSource: Sync {
+    /// every item of the source, sorted so runs are reproducible
+    fn items(&self) -> io::Result<Vec<PathBuf>>;
+
+    /// raw contents of `item`
+    fn fetch(&self, item: &Path) -> io::Result<Vec<u8>>;
+
+    /// `item` decoded, recognized by its contents first and its extension second
+    fn decode(&self, item: &Path) -> io::Result<DynamicImage> {
+        let bytes = self.fetch(item)?;
+        imageformat::decode(item, bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+/// the files below a directory
+pub struct LocalDirectory {
+    pub root: PathBuf,
+    pub options: ScanOptions,
+}
+impl LocalDirectory {
+    /// `items` along with the id every file was looked up with, see `filesysutils::merge_aliases`
+    pub fn files(&self) -> io::Result<Vec<(PathBuf, FileId)>> {
+        // a missing root would otherwise just look empty
+        fs::metadata(&self.root)?;
+        Ok(filesysutils::walk_parallel_files(&self.root, &self.options))
+    }
+}
+impl ImageSource for LocalDirectory {
+    fn items(&self) -> io::Result<Vec<PathBuf>> {
+        Ok(self.files()?.into_iter().map(|(path, _)| path).collect())
+    }
+
+    fn fetch(&self, item: &Path) -> io::Result<Vec<u8>> {
+        fs::read(item)
+    }
+}
+
+/// exactly the files it was given
+pub struct PathList(pub Vec<PathBuf>);
+impl ImageSource for PathList {
+    fn items(&self) -> io::Result<Vec<PathBuf>> {
+        let mut items = self.0.clone();
+        items.sort_unstable();
+        items.dedup();
+        Ok(items)
+    }
+
+    fn fetch(&self, item: &Path) -> io::Result<Vec<u8>> {
+        fs::read(item)
+    }
+}
+
+/// largest response `download` accepts
+pub const MAX_DOWNLOAD_SIZE: u64 = 256 * 1024 * 1024;
+
+/// longest a single request of `download` may take, body included
+pub const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// whether `path` is an http or https url
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// # Description
+/// downloads the contents of `url`
+/// ## Comments
+/// every download shares one agent, so connections to the same server are reused
+/// ## returns
+/// an error for failed requests, error statuses and responses over `MAX_DOWNLOAD_SIZE`
+pub fn download(url: &Path) -> io::Result<Vec<u8>> {
+    static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+    let agent = AGENT.get_or_init(|| {
+        ureq::Agent::new_with_config(
+            ureq::Agent::config_builder()
+                .timeout_global(Some(DOWNLOAD_TIMEOUT))
+                .build(),
+        )
+    });
+    let url = url.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} isn't a url", url),
+        )
+    })?;
+    let mut response = agent.get(url).call().map_err(io::Error::other)?;
+    response
+        .body_mut()
+        .with_config()
+        .limit(MAX_DOWNLOAD_SIZE)
+        .read_to_vec()
+        .map_err(io::Error::other)
+}
+
+/// http and https urls, e.g. the images served by an asset server
+pub struct UrlList {
+    urls: Vec<String>,
+}
+impl UrlList {
+    pub fn new(urls: Vec<String>) -> Self {
+        Self { urls }
+    }
+
+    /// # Description
+    /// reads one url per line
+    /// ## Comments
+    /// blank lines and lines starting with `#` are skipped
+    /// ## returns
+    /// an `InvalidData` error for anything that isn't an http(s) url
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut urls = vec![];
+        for line in reader.lines() {
+            let line = line?;
+            let url = line.trim();
+            if url.is_empty() || url.starts_with('#') {
+                continue;
+            }
+            if !is_url(Path::new(url)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{:?} isn't an http(s) url", url),
+                ));
+            }
+            urls.push(url.to_string());
+        }
+        Ok(Self::new(urls))
+    }
+}
+impl ImageSource for UrlList {
+    fn items(&self) -> io::Result<Vec<PathBuf>> {
+        let mut items = self.urls.iter().map(PathBuf::from).collect::<Vec<_>>();
+        items.sort_unstable();
+        items.dedup();
+        Ok(items)
+    }
+
+    fn fetch(&self, item: &Path) -> io::Result<Vec<u8>> {
+        download(item)
+    }
+}
+
+/// # Description
+/// hashes every item of `source` in parallel, entries come back sorted by item
+/// ## Comments
+/// items that can't be fetched or decoded are reported on stderr and left out,
+/// `on_hashed` sees every entry as soon as it is ready
+/// ## returns
+/// the error of listing the source
+pub fn hash_source(
+    source: &dyn ImageSource,
+    config: &HashConfig,
+    on_hashed: &(dyn Fn(&ImageEntry) + Sync),
+) -> io::Result<Vec<ImageEntry>> {
+    let entries = source
+        .items()?
+        .into_par_iter()
+        .filter(|_| !interrupt::requested())
+        .filter_map(|item| {
+            let img = match source.decode(&item) {
+                Ok(img) => img,
+                Err(err) => {
+                    eprintln!("failed to load {:?}: {}", item, err);
+                    return None;
+                }
+            };
+            let entry = ImageEntry {
+                hash: config.hash(&img)?,
+                path: item,
+                aliases: vec![],
+            };
+            on_hashed(&entry);
+            Some(entry)
+        })
+        .collect();
+    Ok(entries)
+}
+
+#[test]
+fn sources_agree_on_local_and_served_images() {
+    let test_set = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_set");
+    let config = HashConfig::new(HashType::AHASH);
+    let local = LocalDirectory {
+        root: test_set.clone(),
+        options: ScanOptions::default(),
+    };
+    let names = ["a.png", "b.jpg"];
+    assert!(local.items().unwrap().contains(&test_set.join(names[1])));
+    // the small ones, decoding is slow in debug builds
+    let listed = PathList(names.iter().rev().map(|name| test_set.join(name)).collect());
+    let local_entries = hash_source(&listed, &config, &|_| {}).unwrap();
+    assert_eq!(local_entries.len(), names.len());
+
+    let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+    let address = server.server_addr().to_ip().unwrap();
+    let served = test_set.clone();
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let file = served.join(request.url().trim_start_matches('/'));
+            let _ = match std::fs::File::open(file) {
+                Ok(file) => request.respond(tiny_http::Response::from_file(file)),
+                Err(_) => request.respond(tiny_http::Response::empty(404)),
+            };
+        }
+    });
+    let list = names
+        .iter()
+        .rev()
+        .map(|name| format!("http://{}/{}\n", address, name))
+        .collect::<String>();
+    let urls = UrlList::from_reader(format!("# assets\n\n{}", list).as_bytes()).unwrap();
+    let url_entries = hash_source(&urls, &config, &|_| {}).unwrap();
+    let hashes =
+        |entries: &[ImageEntry]| entries.iter().map(|entry| entry.hash).collect::<Vec<_>>();
+    assert_eq!(hashes(&url_entries), hashes(&local_entries));
+    let missing = PathBuf::from(format!("http://{}/missing.png", address));
+    assert!(download(&missing).is_err());
+    assert!(UrlList::from_reader(&b"/etc/passwd\n"[..]).is_err());
+}
@@ -0,0 +1,178 @@
+//! writes a run into a sqlite database so it can be joined with other data in plain sql
+use super::*;
+use crate::index::ImageIndex;
+use rusqlite::{params, Connection};
+use std::{fs, io};
+
+const SCHEMA: &str = "
+    CREATE TABLE meta (
+        key   TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE images (
+        id            INTEGER PRIMARY KEY,
+        path          TEXT NOT NULL,
+        size          INTEGER,
+        width         INTEGER,
+        height        INTEGER,
+        hash          TEXT NOT NULL,
+        tour_position INTEGER
+    );
+    CREATE TABLE aliases (
+        image_id INTEGER NOT NULL REFERENCES images(id),
+        path     TEXT NOT NULL
+    );
+    CREATE TABLE groups (
+        id   INTEGER PRIMARY KEY,
+        hash TEXT NOT NULL,
+        size INTEGER NOT NULL
+    );
+    CREATE TABLE memberships (
+        group_id INTEGER NOT NULL REFERENCES groups(id),
+        image_id INTEGER NOT NULL REFERENCES images(id),
+        PRIMARY KEY (group_id, image_id)
+    );
+    CREATE TABLE mst_edges (
+        a        INTEGER NOT NULL REFERENCES images(id),
+        b        INTEGER NOT NULL REFERENCES images(id),
+        distance INTEGER NOT NULL
+    );
+    CREATE INDEX memberships_by_image ON memberships(image_id);
+    CREATE INDEX aliases_by_image ON aliases(image_id);
+";
+
+/// # Description
+/// writes `index` into a new sqlite database at `path`, replacing any existing file
+/// ## Comments
+/// - image ids are indices into `image_info_list`, group ids are the same `GroupID`s as the index
+/// - `aliases` holds the other paths of an image, one row each
+/// - hashes are stored as 16 hex digit text because sqlite integers are signed
+/// - size and dimensions are read from the files when they are reachable and left NULL otherwise
+pub fn write_sqlite<P: AsRef<Path>>(index: &ImageIndex, path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    write_tables(index, path).map_err(io::Error::other)
+}
+
+fn write_tables(index: &ImageIndex, path: &Path) -> rusqlite::Result<()> {
+    let mut connection = Connection::open(path)?;
+    connection.execute_batch(SCHEMA)?;
+    let transaction = connection.transaction()?;
+
+    {
+        let mut insert_meta =
+            transaction.prepare("INSERT INTO meta (key, value) VALUES (?1, ?2)")?;
+        let meta = [
+            ("index_version", index.version.to_string()),
+            ("hash_algorithm", index.config.algorithm.name().to_string()),
+            ("hash_grid_size", index.config.grid_size.to_string()),
+            ("hash_filter", index.config.filter.name().to_string()),
+            ("hash_version", index.config.version.clone()),
+            ("group_threshold", index.group_threshold.to_string()),
+        ];
+        for (key, value) in meta {
+            insert_meta.execute(params![key, value])?;
+        }
+
+        let mut tour_position = vec![None; index.image_info_list.len()];
+        for (position, &idx) in index.circuit.iter().enumerate() {
+            tour_position[idx] = Some(position as i64);
+        }
+        let mut insert_image = transaction.prepare(
+            "INSERT INTO images (id, path, size, width, height, hash, tour_position)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        for (idx, entry) in index.image_info_list.iter().enumerate() {
+            let size = fs::metadata(&entry.path).ok().map(|m| m.len() as i64);
+            let (width, height) = imageformat::dimensions(&entry.path)
+                .ok()
+                .map(|(w, h)| (Some(w), Some(h)))
+                .unwrap_or_default();
+            insert_image.execute(params![
+                idx as i64,
+                entry.path.to_string_lossy(),
+                size,
+                width,
+                height,
+                hexhash::to_hex(entry.hash),
+                tour_position[idx],
+            ])?;
+        }
+
+        let mut insert_alias =
+            transaction.prepare("INSERT INTO aliases (image_id, path) VALUES (?1, ?2)")?;
+        for (idx, entry) in index.image_info_list.iter().enumerate() {
+            for alias in &entry.aliases {
+                insert_alias.execute(params![idx as i64, alias.to_string_lossy()])?;
+            }
+        }
+
+        let mut insert_group =
+            transaction.prepare("INSERT INTO groups (id, hash, size) VALUES (?1, ?2, ?3)")?;
+        let mut insert_member =
+            transaction.prepare("INSERT INTO memberships (group_id, image_id) VALUES (?1, ?2)")?;
+        for (&group, info) in &index.group_table {
+            insert_group.execute(params![
+                group as i64,
+                hexhash::to_hex(info.hash),
+                info.similar_images.len() as i64
+            ])?;
+            for image in &info.similar_images {
+                insert_member.execute(params![group as i64, image.image_idx as i64])?;
+            }
+        }
+
+        let mut insert_edge =
+            transaction.prepare("INSERT INTO mst_edges (a, b, distance) VALUES (?1, ?2, ?3)")?;
+        for (a, b, distance) in index.mst.iter().flat_map(|mst| mst.edges()) {
+            insert_edge.execute(params![a as i64, b as i64, distance as i64])?;
+        }
+    }
+
+    transaction.commit()
+}
+
+#[test]
+fn sqlite_tables_are_joinable() {
+    let entries = ["test_set/a.png", "test_set/b.jpg", "missing.png"]
+        .iter()
+        .enumerate()
+        .map(|(i, path)| ImageEntry {
+            hash: i as u64,
+            path: Path::new(env!("CARGO_MANIFEST_DIR")).join(path),
+            aliases: (i == 1)
+                .then(|| PathBuf::from("link.jpg"))
+                .into_iter()
+                .collect(),
+        })
+        .collect();
+    let index = ImageIndex::build(perceptual::HashConfig::default(), entries, 100, 0);
+    let dir = TempDir::new("sqlite");
+    let path = dir.join("index.sqlite");
+    write_sqlite(&index, &path).unwrap();
+
+    let connection = Connection::open(&path).unwrap();
+    let (members, with_size): (i64, i64) = connection
+        .query_row(
+            "SELECT COUNT(*), COUNT(images.size) FROM memberships
+             JOIN images ON images.id = memberships.image_id",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    let edges: i64 = connection
+        .query_row("SELECT COUNT(*) FROM mst_edges", [], |row| row.get(0))
+        .unwrap();
+    let aliased: String = connection
+        .query_row(
+            "SELECT images.path FROM aliases JOIN images ON images.id = aliases.image_id
+             WHERE aliases.path = 'link.jpg'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert!(aliased.ends_with("b.jpg"));
+    assert_eq!((members, with_size, edges), (3, 2, 2));
+}
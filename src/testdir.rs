@@ -0,0 +1,36 @@
+//! scratch directories for tests
+use super::*;
+use std::{fs, ops::Deref};
+
+/// # Description
+/// a fresh directory under the system temp dir, removed again when dropped
+/// ## Comments
+/// the name is suffixed with the process id so concurrent test runs don't collide,
+/// dropping also happens while a failed assertion unwinds, so nothing is left behind
+pub struct TempDir(PathBuf);
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("image_grouper_{}_{}", name, std::process::id()));
+        // left over by a run that was killed
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+impl Deref for TempDir {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,167 @@
+//! inotify based watching of the input directories, filesystem events are gathered into batches of changed paths
+use super::*;
+use notify::{
+    event::{AccessKind, AccessMode, CreateKind, EventKind, ModifyKind},
+    Event, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use path_absolutize::*;
+use std::{
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+/// how often a quiet watch checks for ctrl-c
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// how long a file that was written to but never closed holds up a quiet batch
+const OPEN_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// # Description
+/// recursive watch over a set of directories
+/// ## Comments
+/// - reads and attribute changes are dropped, hashing an image or storing its hash attribute would
+///   otherwise trigger another round of hashing. closing a file after writing it is kept, that's what
+///   tells a finished copy from one still in progress
+/// - paths are reported absolute
+pub struct DirectoryWatch {
+    // events stop once the watcher is dropped
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    ignored: Vec<PathBuf>,
+    debounce: Duration,
+}
+impl DirectoryWatch {
+    /// # Description
+    /// starts watching every directory in `directories` and everything below them
+    /// ## Comments
+    /// events under `ignored` (e.g. the output directory) are never reported,
+    /// a batch is only handed out once no event arrived for `debounce`
+    pub fn new<P: AsRef<Path>>(
+        directories: &[P],
+        ignored: &[PathBuf],
+        debounce: Duration,
+    ) -> notify::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        for directory in directories {
+            watcher.watch(directory.as_ref(), RecursiveMode::Recursive)?;
+        }
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            ignored: ignored.iter().map(|path| absolute(path)).collect(),
+            debounce,
+        })
+    }
+
+    /// # Description
+    /// blocks until a burst of changes has settled
+    /// ## Comments
+    /// settled means no event arrived for `debounce` and every file written to was closed again,
+    /// a file left open stops holding up the batch after `OPEN_WRITE_TIMEOUT` without events
+    /// ## returns
+    /// every path named by the burst, or `None` once ctrl-c was pressed or the watcher died
+    pub fn next_batch(&self) -> Option<HashSet<PathBuf>> {
+        let mut changed = HashSet::new();
+        // files written to since their last close
+        let mut writing = HashSet::new();
+        let mut last_event = None;
+        while !interrupt::requested() {
+            match self.events.recv_timeout(POLL_INTERVAL) {
+                Ok(Ok(event)) => {
+                    match event.kind {
+                        EventKind::Access(AccessKind::Close(AccessMode::Write)) => {}
+                        EventKind::Access(_) | EventKind::Modify(ModifyKind::Metadata(_)) => {
+                            continue
+                        }
+                        _ => {}
+                    }
+                    let paths = event
+                        .paths
+                        .iter()
+                        .map(|path| absolute(path))
+                        .filter(|path| !self.ignored.iter().any(|dir| path.starts_with(dir)))
+                        .collect::<Vec<_>>();
+                    if paths.is_empty() {
+                        continue;
+                    }
+                    last_event = Some(Instant::now());
+                    for path in paths {
+                        match event.kind {
+                            EventKind::Create(CreateKind::File)
+                            | EventKind::Modify(ModifyKind::Data(_)) => {
+                                writing.insert(path.clone());
+                            }
+                            // only a close after writing gets this far
+                            EventKind::Access(_) | EventKind::Remove(_) => {
+                                writing.remove(&path);
+                            }
+                            _ => {}
+                        }
+                        changed.insert(path);
+                    }
+                }
+                Ok(Err(err)) => eprintln!("watch error: {}", err),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return None,
+            }
+            let quiet_for =
+                |time: Duration| last_event.is_some_and(|last: Instant| last.elapsed() >= time);
+            if quiet_for(self.debounce) && (writing.is_empty() || quiet_for(OPEN_WRITE_TIMEOUT)) {
+                return Some(changed);
+            }
+        }
+        None
+    }
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    path.absolutize()
+        .map(|path| path.into_owned())
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// # Description
+/// whether `path` is one of the `changed` paths or lies under one of them
+/// ## Comments
+/// archive members count as changed along with their archive
+pub fn is_changed(changed: &HashSet<PathBuf>, path: &Path) -> bool {
+    let path = archive::split_member_path(path).map_or(path, |(archive, _)| archive);
+    absolute(path)
+        .ancestors()
+        .any(|ancestor| changed.contains(ancestor))
+}
+
+#[test]
+fn watch_reports_settled_changes() {
+    let dir = TempDir::new("watch");
+    let output = dir.join("sorted");
+    std::fs::create_dir_all(&output).unwrap();
+    let watch = DirectoryWatch::new(
+        &[&dir],
+        std::slice::from_ref(&output),
+        Duration::from_millis(100),
+    )
+    .unwrap();
+
+    std::fs::write(output.join("0.png"), b"ignored").unwrap();
+    std::fs::create_dir(dir.join("incoming")).unwrap();
+    std::fs::write(dir.join("incoming").join("a.png"), b"new").unwrap();
+    // a copy that pauses for longer than the debounce is only handed out once it's closed
+    let mut copy = std::fs::File::create(dir.join("b.png")).unwrap();
+    std::io::Write::write_all(&mut copy, b"co").unwrap();
+    let writer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(400));
+        std::io::Write::write_all(&mut copy, b"py").unwrap();
+    });
+    let changed = watch.next_batch().unwrap();
+    assert!(writer.is_finished());
+    writer.join().unwrap();
+    assert!(is_changed(&changed, &dir.join("incoming").join("a.png")));
+    assert!(is_changed(&changed, &dir.join("b.png")));
+    assert!(is_changed(
+        &changed,
+        &archive::member_path(&dir.join("incoming").join("a.cbz"), "1.png")
+    ));
+    assert!(!is_changed(&changed, &output.join("0.png")));
+    assert!(!is_changed(&changed, &dir.join("old.png")));
+}